@@ -1,11 +1,18 @@
 use std::env::current_dir;
 use std::fs::create_dir_all;
 
-use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 use coffee_contract::{
-    msg::{ExecuteMsg, InstantiateMsg, QueryMsg},
-    state::BeverageStat,
+    msg::{
+        BackordersResponse, BatchesResponse, DisputesResponse, EntitlementsResponse, ExecuteMsg,
+        InstantiateMsg, InventoryLedgerResponse, LoyaltyStatusResponse, MenuResponse,
+        OrdersResponse, PointsResponse, PriceRulesResponse, QueryMsg, QuoteResponse,
+        RecipeResponse, ReservationsResponse, RestockReportResponse, RevenueResponse,
+        ScheduledPriceChangesResponse, SubscriptionPlansResponse, SubscriptionResponse,
+        TabsResponse, TierResponse,
+    },
+    state::{BeverageStat, GroupOrder, IngredientStat, Pickup, Voucher},
 };
+use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -17,4 +24,28 @@ fn main() {
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
     export_schema(&schema_for!(BeverageStat), &out_dir);
+    export_schema(&schema_for!(IngredientStat), &out_dir);
+    export_schema(&schema_for!(RecipeResponse), &out_dir);
+    export_schema(&schema_for!(RestockReportResponse), &out_dir);
+    export_schema(&schema_for!(BatchesResponse), &out_dir);
+    export_schema(&schema_for!(InventoryLedgerResponse), &out_dir);
+    export_schema(&schema_for!(RevenueResponse), &out_dir);
+    export_schema(&schema_for!(PriceRulesResponse), &out_dir);
+    export_schema(&schema_for!(ScheduledPriceChangesResponse), &out_dir);
+    export_schema(&schema_for!(QuoteResponse), &out_dir);
+    export_schema(&schema_for!(LoyaltyStatusResponse), &out_dir);
+    export_schema(&schema_for!(TierResponse), &out_dir);
+    export_schema(&schema_for!(PointsResponse), &out_dir);
+    export_schema(&schema_for!(MenuResponse), &out_dir);
+    export_schema(&schema_for!(SubscriptionPlansResponse), &out_dir);
+    export_schema(&schema_for!(SubscriptionResponse), &out_dir);
+    export_schema(&schema_for!(Voucher), &out_dir);
+    export_schema(&schema_for!(EntitlementsResponse), &out_dir);
+    export_schema(&schema_for!(ReservationsResponse), &out_dir);
+    export_schema(&schema_for!(OrdersResponse), &out_dir);
+    export_schema(&schema_for!(Pickup), &out_dir);
+    export_schema(&schema_for!(BackordersResponse), &out_dir);
+    export_schema(&schema_for!(DisputesResponse), &out_dir);
+    export_schema(&schema_for!(TabsResponse), &out_dir);
+    export_schema(&schema_for!(GroupOrder), &out_dir);
 }
@@ -1,14 +1,40 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Uint128,
+    to_binary, Addr, Binary, Deps, DepsMut, Empty, Env, Event, MessageInfo, Order, Response,
+    StdError, StdResult, Storage, Timestamp, Uint128,
 };
 use cw2::set_contract_version;
 use cw20::Cw20Coin;
+use cw_storage_plus::{Bound, U64Key};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
 
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
-use crate::state::{ADMIN, BALANCES, BEVERAGES};
+use crate::msg::{
+    BackordersResponse, BatchesResponse, DisputesResponse, EntitlementsResponse, ExecuteMsg,
+    InstantiateMsg, InventoryLedgerResponse, LoyaltyStatusResponse, MenuBeverage, MenuBundle,
+    MenuResponse, MethodRevenue, OrdersResponse, PointsResponse, PriceRulesResponse, QueryMsg,
+    QuoteResponse, RecipeResponse, ReservationsResponse, RestockItem, RestockReportResponse,
+    RevenueResponse, ScheduledPriceChangesResponse, ShareRequest, SubscriptionPlanInfo,
+    SubscriptionPlansResponse, SubscriptionResponse, TabInfo, TabsResponse, TierResponse,
+};
+use crate::state::{
+    local_month, month_start, Adjustment, AdjustmentReason, Backorder, BaristaOrder, Batch,
+    BeverageStat, Bundle, Discount, Dispute, Entitlement, GroupOrder, GroupShare, LoyaltyRule,
+    OrderStatus, Pickup, PointsConfig, PointsGrant, PriceBreak, PriceChange, PriceRule, PromoCode,
+    PurchaseRecord, RecipeItem, Reservation, ScarcityPricing, Spending, Subscription,
+    SubscriptionPlan, TiersConfig, Voucher, VoucherValue, ADJUSTMENTS, ADJUSTMENT_COUNT, ADMIN,
+    ARBITER, BACKORDERS, BACKORDER_COUNT, BALANCES, BARISTA_ORDERS, BARISTA_ORDER_COUNT, BEVERAGES,
+    BEVERAGE_CAPACITY, BUNDLES, CREDIT_LIMITS, DEFAULT_REFUND_WINDOW_SECONDS,
+    DEFAULT_RESERVATION_BLOCKS, DISPUTES, DISPUTE_COUNT, ENTITLEMENTS, ENTITLEMENT_COUNT,
+    GROUP_ORDERS, GROUP_ORDER_COUNT, INGREDIENTS, LOYALTY_CARDS, LOYALTY_RULE,
+    MIN_PICKUP_SALT_LENGTH, MODIFIERS, OFF_CHAIN_REVENUE, ON_CHAIN_REVENUE, OPERATORS, PICKUPS,
+    POINTS, POINTS_CONFIG, PRICE_CHANGES, PRICE_RULES, PROMO_CODES, PROMO_CODE_USES, PURCHASES,
+    RECIPES, REFUND_WINDOW, RESERVATIONS, RESERVATION_BLOCKS, RESERVATION_COUNT, SCARCITY_PRICING,
+    SPENDINGS, SPEND_WINDOW_SECONDS, SUBSCRIPTIONS, SUBSCRIPTION_PLANS, TABS, TIERS, UTC_OFFSET,
+    VOUCHERS,
+};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:my-first-contract";
@@ -26,6 +52,14 @@ pub fn instantiate(
     create_accounts(&mut deps, &msg.initial_balances)?;
     BALANCES.save(deps.storage, &_env.contract.address, &Uint128::zero())?;
     ADMIN.save(deps.storage, &info.sender)?;
+    ON_CHAIN_REVENUE.save(deps.storage, &Uint128::zero())?;
+    UTC_OFFSET.save(deps.storage, &0)?;
+    LOYALTY_RULE.save(deps.storage, &None)?;
+    TIERS.save(deps.storage, &None)?;
+    POINTS_CONFIG.save(deps.storage, &None)?;
+    RESERVATION_BLOCKS.save(deps.storage, &DEFAULT_RESERVATION_BLOCKS)?;
+    REFUND_WINDOW.save(deps.storage, &DEFAULT_REFUND_WINDOW_SECONDS)?;
+    ARBITER.save(deps.storage, &None)?;
 
     Ok(Response::new()
         .add_attribute("method", "instantiate")
@@ -49,18 +83,195 @@ pub fn execute(
 ) -> Result<Response, ContractError> {
     match msg {
         ExecuteMsg::UpdateBeverage { bev_type, price } => {
-            update_beverage(deps, info, &bev_type, price)
+            update_beverage(deps, _env, info, &bev_type, price)
+        }
+        ExecuteMsg::RefillBeverage {
+            bev_type,
+            amount,
+            expires_at,
+        } => refill_beverage(deps, _env, info, &bev_type, amount, expires_at),
+        ExecuteMsg::WriteOffExpired { bev_type } => write_off_expired(deps, _env, info, &bev_type),
+        ExecuteMsg::AdjustStock {
+            bev_type,
+            delta,
+            reason,
+            expires_at,
+        } => adjust_stock(deps, _env, info, &bev_type, delta, reason, expires_at),
+        ExecuteMsg::UpdateIngredient {
+            ingredient,
+            capacity,
+        } => update_ingredient(deps, info, &ingredient, capacity),
+        ExecuteMsg::RefillIngredient { ingredient, amount } => {
+            refill_ingredient(deps, info, &ingredient, amount)
+        }
+        ExecuteMsg::SetRecipe { bev_type, recipe } => set_recipe(deps, info, &bev_type, recipe),
+        ExecuteMsg::SetLowStockThreshold {
+            bev_type,
+            threshold,
+        } => set_low_stock_threshold(deps, info, &bev_type, threshold),
+        ExecuteMsg::SetIngredientLowStockThreshold {
+            ingredient,
+            threshold,
+        } => set_ingredient_low_stock_threshold(deps, info, &ingredient, threshold),
+        ExecuteMsg::UpdateModifier { modifier, price } => {
+            update_modifier(deps, info, &modifier, price)
+        }
+        ExecuteMsg::SetPriceBreaks { bev_type, breaks } => {
+            set_price_breaks(deps, info, &bev_type, breaks)
+        }
+        ExecuteMsg::SetBeverageCategory { bev_type, category } => {
+            set_beverage_category(deps, info, &bev_type, category)
+        }
+        ExecuteMsg::SetLoyaltyRule { rule } => set_loyalty_rule(deps, info, rule),
+        ExecuteMsg::SetTiers { tiers } => set_tiers(deps, info, tiers),
+        ExecuteMsg::RegisterPromoCode {
+            code_hash,
+            discount,
+            beverages,
+            max_uses,
+            max_uses_per_address,
+            expires_at,
+        } => register_promo_code(
+            deps,
+            info,
+            code_hash,
+            PromoCode {
+                discount,
+                beverages,
+                max_uses,
+                max_uses_per_address,
+                expires_at,
+                uses: 0,
+            },
+        ),
+        ExecuteMsg::RemovePromoCode { code_hash } => remove_promo_code(deps, info, code_hash),
+        ExecuteMsg::Purchase {
+            bev_type,
+            quantity,
+            modifiers,
+            promo_code,
+            pay_with_points,
+            pickup_code_hash,
+        } => purchase(
+            deps,
+            _env,
+            info,
+            PurchaseRequest {
+                bev_type,
+                quantity: quantity.unwrap_or(1),
+                modifiers,
+                promo_code,
+            },
+            pay_with_points,
+            pickup_code_hash,
+        ),
+        ExecuteMsg::CollectPickup { code, salt } => collect_pickup(deps, _env, info, &code, &salt),
+        ExecuteMsg::UpdateBundle { name, bundle } => update_bundle(deps, _env, info, &name, bundle),
+        ExecuteMsg::PurchaseBundle { name, quantity } => {
+            purchase_bundle(deps, _env, info, &name, quantity.unwrap_or(1))
+        }
+        ExecuteMsg::UpdateSubscriptionPlan { name, plan } => {
+            update_subscription_plan(deps, info, &name, plan)
+        }
+        ExecuteMsg::Subscribe { plan, months } => {
+            subscribe(deps, _env, info, &plan, months.unwrap_or(1))
+        }
+        ExecuteMsg::BuyVoucher {
+            code_hash,
+            value,
+            expires_at,
+        } => buy_voucher(deps, _env, info, code_hash, value, expires_at),
+        ExecuteMsg::RedeemVoucher { code } => redeem_voucher(deps, _env, info, &code),
+        ExecuteMsg::RefundVoucher { code_hash } => refund_voucher(deps, _env, code_hash),
+        ExecuteMsg::GiftDrink {
+            bev_type,
+            recipient,
+            quantity,
+        } => gift_drink(deps, _env, info, bev_type, recipient, quantity.unwrap_or(1)),
+        ExecuteMsg::ClaimDrink { id } => claim_drink(deps, _env, info, id),
+        ExecuteMsg::SetReservationBlocks { blocks } => set_reservation_blocks(deps, info, blocks),
+        ExecuteMsg::Reserve { bev_type, quantity } => reserve(deps, _env, info, bev_type, quantity),
+        ExecuteMsg::Collect { id } => collect(deps, _env, info, id),
+        ExecuteMsg::ExpireReservations { limit } => expire_reservations(deps, _env, limit),
+        ExecuteMsg::PlaceOrder {
+            bev_type,
+            quantity,
+            modifiers,
+        } => place_order(deps, _env, info, bev_type, quantity.unwrap_or(1), modifiers),
+        ExecuteMsg::AdvanceOrder { id } => advance_order(deps, _env, info, id),
+        ExecuteMsg::CancelOrder { id } => cancel_order(deps, info, id),
+        ExecuteMsg::Backorder { bev_type, quantity } => {
+            backorder(deps, _env, info, bev_type, quantity.unwrap_or(1))
         }
-        ExecuteMsg::RefillBeverage { bev_type, amount } => {
-            refill_beverage(deps, info, &bev_type, amount)
+        ExecuteMsg::CancelBackorder { bev_type, id } => cancel_backorder(deps, info, &bev_type, id),
+        ExecuteMsg::SetRefundWindow { seconds } => set_refund_window(deps, info, seconds),
+        ExecuteMsg::SetArbiter { arbiter } => set_arbiter(deps, info, arbiter),
+        ExecuteMsg::RequestRefund {
+            bev_type,
+            purchased_at,
+            reason,
+        } => request_refund(deps, _env, info, &bev_type, purchased_at, reason),
+        ExecuteMsg::ResolveRefund {
+            id,
+            approve,
+            restock,
+        } => resolve_refund(deps, _env, info, id, approve, restock),
+        ExecuteMsg::SetCreditLimit { address, limit } => {
+            set_credit_limit(deps, info, address, limit)
         }
-        ExecuteMsg::Purchase { bev_type } => purchase(deps, _env, info, &bev_type),
+        ExecuteMsg::SettleTab { amount } => settle_tab(deps, _env, info, amount),
+        ExecuteMsg::CreateGroupOrder {
+            bev_type,
+            quantity,
+            modifiers,
+            shares,
+            expires_at,
+        } => create_group_order(
+            deps,
+            _env,
+            info,
+            PurchaseRequest {
+                bev_type,
+                quantity: quantity.unwrap_or(1),
+                modifiers,
+                promo_code: None,
+            },
+            shares,
+            expires_at,
+        ),
+        ExecuteMsg::FundGroupOrder { id } => fund_group_order(deps, _env, info, id),
+        ExecuteMsg::ExpireGroupOrder { id } => expire_group_order(deps, _env, id),
+        ExecuteMsg::SetPointsConfig { config } => set_points_config(deps, info, config),
+        ExecuteMsg::RecordExternalSale {
+            bev_type,
+            quantity,
+            amount,
+            method,
+        } => record_external_sale(deps, _env, info, &bev_type, quantity, amount, &method),
+        ExecuteMsg::UpdateOperators { add, remove } => update_operators(deps, info, add, remove),
+        ExecuteMsg::SetPriceRules { bev_type, rules } => {
+            set_price_rules(deps, info, &bev_type, rules)
+        }
+        ExecuteMsg::SetUtcOffset { minutes } => set_utc_offset(deps, info, minutes),
+        ExecuteMsg::SetScarcityPricing { bev_type, pricing } => {
+            set_scarcity_pricing(deps, info, &bev_type, pricing)
+        }
+        ExecuteMsg::SchedulePriceChange {
+            bev_type,
+            new_price,
+            effective_at,
+        } => schedule_price_change(deps, _env, info, &bev_type, new_price, effective_at),
+        ExecuteMsg::CancelScheduledChange {
+            bev_type,
+            effective_at,
+        } => cancel_scheduled_change(deps, info, &bev_type, effective_at),
         ExecuteMsg::WithdrawIncome {} => withdraw_income(deps, _env, info),
     }
 }
 
 fn update_beverage(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
     bev_type: &str,
     price: Uint128,
@@ -70,6 +281,9 @@ fn update_beverage(
         return Err(ContractError::Unauthorized {});
     }
 
+    // settle the due changes so that they don't override the new price later
+    apply_price_changes(deps.storage, env.block.time, bev_type)?;
+
     BEVERAGES.update(
         deps.storage,
         bev_type,
@@ -87,9 +301,11 @@ fn update_beverage(
 
 fn refill_beverage(
     deps: DepsMut,
+    env: Env,
     info: MessageInfo,
     bev_type: &str,
     amount: u8,
+    expires_at: Option<Timestamp>,
 ) -> Result<Response, ContractError> {
     let admin = ADMIN.load(deps.storage)?;
     if info.sender.ne(&admin) {
@@ -101,378 +317,6271 @@ fn refill_beverage(
     BEVERAGES.update(
         deps.storage,
         bev_type,
-        |stat_opt| -> Result<_, ContractError> { stat_opt.unwrap().refill(amount) },
+        |stat_opt| -> Result<_, ContractError> {
+            stat_opt.unwrap().refill(amount, env.block.time, expires_at)
+        },
     )?;
+    let served = serve_backorders(deps.storage, &env, bev_type, amount)?;
 
     Ok(Response::new()
         .add_attribute("action", "refill")
         .add_attribute("beverage_type", bev_type)
-        .add_attribute("amount", amount.to_string()))
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("backorders_served", served.to_string()))
 }
 
-fn purchase(
+/// Serves the backorders of the beverage in the order of placement with the
+/// refilled items. The items are held for the buyers as entitlements.
+fn serve_backorders(
+    storage: &mut dyn Storage,
+    env: &Env,
+    bev_type: &str,
+    refilled: u8,
+) -> Result<u32, ContractError> {
+    // every backorder takes at least one of the refilled items
+    let backorders: Vec<Backorder> = BACKORDERS
+        .prefix(bev_type)
+        .range(storage, None, None, Order::Ascending)
+        .take(refilled.into())
+        .map(|item| item.map(|(_, backorder)| backorder))
+        .collect::<StdResult<_>>()?;
+
+    let mut stat = BEVERAGES.load(storage, bev_type)?;
+    let mut left = refilled;
+    let mut served = 0;
+    for backorder in backorders {
+        if backorder.quantity > left || backorder.quantity > stat.available(env.block.time) {
+            break;
+        }
+        let batches = stat.hold(backorder.quantity, env.block.time)?;
+        left -= backorder.quantity;
+
+        BACKORDERS.remove(storage, (bev_type, backorder.id.into()));
+        book_sale(storage, env, &backorder.buyer, backorder.paid)?;
+        grant_entitlement(
+            storage,
+            env.block.time,
+            &backorder.buyer,
+            backorder.buyer.clone(),
+            bev_type,
+            backorder.quantity,
+            batches,
+        )?;
+        served += 1;
+    }
+    BEVERAGES.save(storage, bev_type, &stat)?;
+    Ok(served)
+}
+
+fn write_off_expired(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     bev_type: &str,
 ) -> Result<Response, ContractError> {
-    let price = BEVERAGES.load(deps.storage, bev_type)?.price;
-
-    BEVERAGES.may_load(deps.storage, bev_type)?;
-
-    BALANCES.update(
-        deps.storage,
-        &info.sender,
-        |balance: Option<Uint128>| -> Result<_, ContractError> {
-            balance
-                .unwrap()
-                .checked_sub(price)
-                .or(Err(ContractError::NotEnoughCoins {}))
-        },
-    )?;
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
 
-    BALANCES.update(
-        deps.storage,
-        &_env.contract.address,
-        |balance: Option<Uint128>| -> StdResult<_> { Ok(balance.unwrap() + price) },
-    )?;
+    let mut stat = BEVERAGES.load(deps.storage, bev_type)?;
+    let written_off = stat.write_off_expired(env.block.time);
+    BEVERAGES.save(deps.storage, bev_type, &stat)?;
 
-    BEVERAGES.update(deps.storage, bev_type, |stat_opt| stat_opt.unwrap().sell())?;
+    if written_off > 0 {
+        log_adjustment(
+            deps,
+            &env,
+            info.sender,
+            bev_type,
+            -i16::from(written_off),
+            AdjustmentReason::Spoilage,
+        )?;
+    }
 
-    Ok(Response::default())
+    Ok(Response::new()
+        .add_attribute("action", "write_off_expired")
+        .add_attribute("beverage_type", bev_type)
+        .add_attribute("amount", written_off.to_string()))
 }
 
-fn withdraw_income(deps: DepsMut, _env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+fn adjust_stock(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    bev_type: &str,
+    delta: i16,
+    reason: AdjustmentReason,
+    expires_at: Option<Timestamp>,
+) -> Result<Response, ContractError> {
     let admin = ADMIN.load(deps.storage)?;
     if info.sender.ne(&admin) {
         return Err(ContractError::Unauthorized {});
     }
 
-    let cur_contract_balance = BALANCES.load(deps.storage, &_env.contract.address)?;
+    if delta == 0 {
+        return Err(ContractError::ZeroAdjustment {});
+    }
 
-    BALANCES.update(
+    let stat = BEVERAGES.load(deps.storage, bev_type)?;
+    let (stat, added) = if delta > 0 {
+        let amount = u8::try_from(delta).or(Err(ContractError::BeverageNumberExceed {}))?;
+        (stat.refill(amount, env.block.time, expires_at)?, amount)
+    } else {
+        let amount =
+            u8::try_from(delta.unsigned_abs()).or(Err(ContractError::BeverageIsOver {}))?;
+        (stat.remove(amount)?, 0)
+    };
+    BEVERAGES.save(deps.storage, bev_type, &stat)?;
+
+    // found items serve the waiting buyers just like a refill
+    let served = if added > 0 {
+        Some(serve_backorders(deps.storage, &env, bev_type, added)?)
+    } else {
+        None
+    };
+    let id = log_adjustment(deps, &env, info.sender, bev_type, delta, reason)?;
+
+    let mut response = Response::new()
+        .add_attribute("action", "adjust_stock")
+        .add_attribute("beverage_type", bev_type)
+        .add_attribute("delta", delta.to_string())
+        .add_attribute("adjustment_id", id.to_string());
+    if let Some(served) = served {
+        response = response.add_attribute("backorders_served", served.to_string());
+    }
+    Ok(response)
+}
+
+fn log_adjustment(
+    deps: DepsMut,
+    env: &Env,
+    by: Addr,
+    bev_type: &str,
+    delta: i16,
+    reason: AdjustmentReason,
+) -> StdResult<u64> {
+    let id = ADJUSTMENT_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+    ADJUSTMENT_COUNT.save(deps.storage, &id)?;
+    ADJUSTMENTS.save(
         deps.storage,
-        &info.sender,
-        |balance: Option<Uint128>| -> StdResult<_> {
-            Ok(balance.unwrap_or_default() + cur_contract_balance)
+        id.into(),
+        &Adjustment {
+            id,
+            bev_type: bev_type.to_string(),
+            delta,
+            reason,
+            by,
+            at: env.block.time,
         },
     )?;
+    Ok(id)
+}
 
-    BALANCES.update(
+fn update_ingredient(
+    deps: DepsMut,
+    info: MessageInfo,
+    ingredient: &str,
+    capacity: Uint128,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    INGREDIENTS.update(
         deps.storage,
-        &_env.contract.address,
-        |balance: Option<Uint128>| -> StdResult<_> {
-            Ok(balance.unwrap_or_default() - cur_contract_balance)
+        ingredient,
+        |stat_opt| -> Result<_, ContractError> {
+            let mut stat = stat_opt.unwrap_or_default();
+            if stat.amount > capacity {
+                return Err(ContractError::IngredientCapacityExceed {});
+            }
+            stat.capacity = capacity;
+            Ok(stat)
         },
     )?;
 
     Ok(Response::new()
-        .add_attribute("action", "winthdraw_income")
-        .add_attribute("amount", cur_contract_balance)
-        .add_attribute("recipient", admin))
+        .add_attribute("action", "update_ingredient")
+        .add_attribute("ingredient", ingredient)
+        .add_attribute("capacity", capacity))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::Balance { address } => {
-            let address = deps.api.addr_validate(&address)?;
-            let balance = BALANCES
-                .may_load(deps.storage, &address)?
-                .unwrap_or_default();
-            Ok(to_binary(&cw20::BalanceResponse { balance })?)
-        }
-        QueryMsg::BeverageStat { bev_type } => {
-            let bev_stat = BEVERAGES.load(deps.storage, &bev_type)?;
-            Ok(to_binary(&bev_stat)?)
-        }
+fn refill_ingredient(
+    deps: DepsMut,
+    info: MessageInfo,
+    ingredient: &str,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::state::BeverageStat;
+    let stat = INGREDIENTS.load(deps.storage, ingredient)?.refill(amount)?;
+    INGREDIENTS.save(deps.storage, ingredient, &stat)?;
 
-    use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{from_binary, StdError};
+    Ok(Response::new()
+        .add_attribute("action", "refill_ingredient")
+        .add_attribute("ingredient", ingredient)
+        .add_attribute("amount", amount))
+}
 
-    fn do_intantiate(deps: DepsMut, info: MessageInfo) -> Response {
-        let initial_balances = vec![Cw20Coin {
-            address: "addr0".to_string(),
-            amount: Uint128::from(10_u16),
-        }];
+fn set_recipe(
+    deps: DepsMut,
+    info: MessageInfo,
+    bev_type: &str,
+    recipe: Vec<RecipeItem>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
 
-        let msg = InstantiateMsg {
-            initial_balances,
-        };
+    BEVERAGES.load(deps.storage, bev_type)?;
+    for item in &recipe {
+        if item.amount.is_zero() {
+            return Err(ContractError::InvalidRecipe {});
+        }
+        INGREDIENTS.load(deps.storage, &item.ingredient)?;
+    }
+    RECIPES.save(deps.storage, bev_type, &recipe)?;
 
-        // we can just call .unwrap() to assert this was a success
-        let res = instantiate(deps, mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
+    Ok(Response::new()
+        .add_attribute("action", "set_recipe")
+        .add_attribute("beverage_type", bev_type))
+}
 
-        res
+fn set_low_stock_threshold(
+    deps: DepsMut,
+    info: MessageInfo,
+    bev_type: &str,
+    threshold: Option<u8>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
     }
 
-    fn query_balance(deps: DepsMut, addr: &str) -> cw20::BalanceResponse {
-        let res = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::Balance {
-                address: addr.to_string(),
-            },
-        )
-        .unwrap();
-        from_binary(&res).unwrap()
+    let mut stat = BEVERAGES.load(deps.storage, bev_type)?;
+    stat.low_stock = threshold;
+    BEVERAGES.save(deps.storage, bev_type, &stat)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_low_stock_threshold")
+        .add_attribute("beverage_type", bev_type)
+        .add_attribute("threshold", format!("{:?}", threshold)))
+}
+
+fn set_ingredient_low_stock_threshold(
+    deps: DepsMut,
+    info: MessageInfo,
+    ingredient: &str,
+    threshold: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
     }
 
-    #[test]
-    fn proper_initialization() {
-        let mut deps = mock_dependencies(&[]);
-        let info = mock_info("admin", &[]);
+    let mut stat = INGREDIENTS.load(deps.storage, ingredient)?;
+    stat.low_stock = threshold;
+    INGREDIENTS.save(deps.storage, ingredient, &stat)?;
 
-        do_intantiate(deps.as_mut(), info);
+    Ok(Response::new()
+        .add_attribute("action", "set_ingredient_low_stock_threshold")
+        .add_attribute("ingredient", ingredient)
+        .add_attribute("threshold", format!("{:?}", threshold)))
+}
 
-        let value: cw20::BalanceResponse = query_balance(deps.as_mut(), "addr0");
-        assert_eq!(Uint128::from(10_u32), value.balance);
+/// Takes every ingredient of the beverage recipe out of the store.
+/// Beverages without a recipe are sold by the cup only.
+/// Returns `low_stock` events for the ingredients which are running out.
+fn consume_ingredients(
+    deps: &mut DepsMut,
+    bev_type: &str,
+    quantity: u8,
+) -> Result<Vec<Event>, ContractError> {
+    let recipe = RECIPES
+        .may_load(deps.storage, bev_type)?
+        .unwrap_or_default();
+    let mut events = vec![];
+    for item in recipe {
+        let stat = INGREDIENTS
+            .load(deps.storage, &item.ingredient)?
+            .consume(&item.ingredient, item.amount * Uint128::from(quantity))?;
+        INGREDIENTS.save(deps.storage, &item.ingredient, &stat)?;
+        if stat.is_low() {
+            events.push(
+                Event::new("low_stock")
+                    .add_attribute("ingredient", &item.ingredient)
+                    .add_attribute("amount", stat.amount)
+                    .add_attribute("threshold", stat.low_stock.unwrap_or_default()),
+            );
+        }
     }
+    Ok(events)
+}
 
-    #[test]
-    fn test_update_beverage() {
-        let mut deps = mock_dependencies(&[]);
-        let info = mock_info("admin", &[]);
+/// Takes sold items out of the machine along with their ingredients.
+/// Every sale, paid on-chain or not, goes through here to keep the stock truthful.
+fn sell_beverage(
+    deps: &mut DepsMut,
+    env: &Env,
+    bev_type: &str,
+    quantity: u8,
+) -> Result<Vec<Event>, ContractError> {
+    if quantity == 0 {
+        return Err(ContractError::ZeroQuantity {});
+    }
 
-        do_intantiate(deps.as_mut(), info);
+    let mut stat = BEVERAGES.load(deps.storage, bev_type)?;
+    for _ in 0..quantity {
+        stat = stat.sell(env.block.time)?;
+    }
+    BEVERAGES.save(deps.storage, bev_type, &stat)?;
 
-        execute(
-            deps.as_mut(),
-            mock_env(),
-            mock_info("admin", &[]),
-            ExecuteMsg::UpdateBeverage {
-                bev_type: "americano".to_string(),
-                price: Uint128::from(2_u16),
-            },
-        )
-        .unwrap();
+    let mut events = consume_ingredients(deps, bev_type, quantity)?;
+    if let Some(event) = low_stock_event(bev_type, &stat) {
+        events.insert(0, event);
+    }
+    Ok(events)
+}
 
-        let res = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::BeverageStat {
-                bev_type: "americano".to_string(),
-            },
-        )
-        .unwrap();
+/// Hands out items taken by `hold`. Held items which went off in the meantime
+/// are written off and replaced from the current stock.
+fn hand_out_held(
+    deps: &mut DepsMut,
+    env: &Env,
+    by: &Addr,
+    bev_type: &str,
+    batches: &[Batch],
+) -> Result<Vec<Event>, ContractError> {
+    let quantity = batches.iter().map(|batch| batch.amount).sum();
+    let expired: u8 = batches
+        .iter()
+        .filter(|batch| batch.is_expired(env.block.time))
+        .map(|batch| batch.amount)
+        .sum();
 
-        let value: BeverageStat = from_binary(&res).unwrap();
-        assert_eq!(Uint128::from(2_u16), value.price)
+    let mut stat = BEVERAGES.load(deps.storage, bev_type)?;
+    stat.dispense_held(quantity);
+    if expired == 0 {
+        BEVERAGES.save(deps.storage, bev_type, &stat)?;
+        return consume_ingredients(deps, bev_type, quantity);
     }
 
-    #[test]
-    fn test_refill_beverage() {
-        let mut deps = mock_dependencies(&[]);
-        let info = mock_info("admin", &[]);
+    for _ in 0..expired {
+        stat = stat.sell(env.block.time)?;
+    }
+    BEVERAGES.save(deps.storage, bev_type, &stat)?;
+    log_adjustment(
+        deps.branch(),
+        env,
+        by.clone(),
+        bev_type,
+        -i16::from(expired),
+        AdjustmentReason::Spoilage,
+    )?;
 
-        do_intantiate(deps.as_mut(), info);
+    let mut events = consume_ingredients(deps, bev_type, quantity)?;
+    if let Some(event) = low_stock_event(bev_type, &stat) {
+        events.insert(0, event);
+    }
+    Ok(events)
+}
 
-        execute(
-            deps.as_mut(),
-            mock_env(),
-            mock_info("admin", &[]),
-            ExecuteMsg::UpdateBeverage {
-                bev_type: "americano".to_string(),
-                price: Uint128::from(2_u16),
-            },
-        )
-        .unwrap();
+fn low_stock_event(bev_type: &str, stat: &BeverageStat) -> Option<Event> {
+    if !stat.is_low() {
+        return None;
+    }
+    Some(
+        Event::new("low_stock")
+            .add_attribute("beverage_type", bev_type)
+            .add_attribute("amount", stat.amount.to_string())
+            .add_attribute("threshold", stat.low_stock.unwrap_or_default().to_string()),
+    )
+}
 
-        execute(
-            deps.as_mut(),
-            mock_env(),
-            mock_info("admin", &[]),
-            ExecuteMsg::RefillBeverage {
-                bev_type: "americano".to_string(),
-                amount: 20,
-            },
-        )
-        .unwrap();
+fn set_price_rules(
+    deps: DepsMut,
+    info: MessageInfo,
+    bev_type: &str,
+    rules: Vec<PriceRule>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
 
-        let res = query(
-            deps.as_ref(),
-            mock_env(),
-            QueryMsg::BeverageStat {
-                bev_type: "americano".to_string(),
-            },
-        )
-        .unwrap();
+    BEVERAGES.load(deps.storage, bev_type)?;
+    for rule in &rules {
+        rule.validate()?;
+    }
+    PRICE_RULES.save(deps.storage, bev_type, &rules)?;
 
-        let value: BeverageStat = from_binary(&res).unwrap();
+    Ok(Response::new()
+        .add_attribute("action", "set_price_rules")
+        .add_attribute("beverage_type", bev_type)
+        .add_attribute("rules", rules.len().to_string()))
+}
+
+fn set_utc_offset(
+    deps: DepsMut,
+    info: MessageInfo,
+    minutes: i16,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if minutes.abs() > 14 * 60 {
+        return Err(ContractError::InvalidUtcOffset {});
+    }
+    UTC_OFFSET.save(deps.storage, &minutes)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_utc_offset")
+        .add_attribute("minutes", minutes.to_string()))
+}
+
+fn set_scarcity_pricing(
+    deps: DepsMut,
+    info: MessageInfo,
+    bev_type: &str,
+    pricing: Option<ScarcityPricing>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    BEVERAGES.load(deps.storage, bev_type)?;
+    match &pricing {
+        Some(pricing) => {
+            pricing.validate()?;
+            SCARCITY_PRICING.save(deps.storage, bev_type, pricing)?;
+        }
+        None => SCARCITY_PRICING.remove(deps.storage, bev_type),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_scarcity_pricing")
+        .add_attribute("beverage_type", bev_type)
+        .add_attribute("enabled", pricing.is_some().to_string()))
+}
+
+fn schedule_price_change(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    bev_type: &str,
+    new_price: Uint128,
+    effective_at: Timestamp,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if effective_at <= env.block.time {
+        return Err(ContractError::PastPriceChange {});
+    }
+    BEVERAGES.load(deps.storage, bev_type)?;
+
+    let mut changes = PRICE_CHANGES
+        .may_load(deps.storage, bev_type)?
+        .unwrap_or_default();
+    // a change scheduled for the same moment is replaced
+    changes.retain(|change| change.effective_at != effective_at);
+    let index = changes
+        .iter()
+        .position(|change| change.effective_at > effective_at)
+        .unwrap_or(changes.len());
+    changes.insert(
+        index,
+        PriceChange {
+            new_price,
+            effective_at,
+        },
+    );
+    PRICE_CHANGES.save(deps.storage, bev_type, &changes)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "schedule_price_change")
+        .add_attribute("beverage_type", bev_type)
+        .add_attribute("new_price", new_price)
+        .add_attribute("effective_at", effective_at.to_string()))
+}
+
+fn cancel_scheduled_change(
+    deps: DepsMut,
+    info: MessageInfo,
+    bev_type: &str,
+    effective_at: Timestamp,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut changes = PRICE_CHANGES
+        .may_load(deps.storage, bev_type)?
+        .unwrap_or_default();
+    let index = changes
+        .iter()
+        .position(|change| change.effective_at == effective_at)
+        .ok_or(ContractError::PriceChangeNotFound {})?;
+    changes.remove(index);
+    PRICE_CHANGES.save(deps.storage, bev_type, &changes)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_scheduled_change")
+        .add_attribute("beverage_type", bev_type)
+        .add_attribute("effective_at", effective_at.to_string()))
+}
+
+/// Loads the beverage with the price of the latest scheduled change which is already due.
+fn load_beverage(storage: &dyn Storage, now: Timestamp, bev_type: &str) -> StdResult<BeverageStat> {
+    let mut stat = BEVERAGES.load(storage, bev_type)?;
+    let changes = PRICE_CHANGES
+        .may_load(storage, bev_type)?
+        .unwrap_or_default();
+    if let Some(change) = changes
+        .iter()
+        .rev()
+        .find(|change| change.effective_at <= now)
+    {
+        stat.price = change.new_price;
+    }
+    Ok(stat)
+}
+
+/// Persists the scheduled price changes which are already due.
+fn apply_price_changes(storage: &mut dyn Storage, now: Timestamp, bev_type: &str) -> StdResult<()> {
+    let mut changes = PRICE_CHANGES
+        .may_load(storage, bev_type)?
+        .unwrap_or_default();
+    let due = changes
+        .iter()
+        .take_while(|change| change.effective_at <= now)
+        .count();
+    if due > 0 {
+        let stat = load_beverage(storage, now, bev_type)?;
+        BEVERAGES.save(storage, bev_type, &stat)?;
+        changes.drain(..due);
+        PRICE_CHANGES.save(storage, bev_type, &changes)?;
+    }
+    Ok(())
+}
+
+fn update_modifier(
+    deps: DepsMut,
+    info: MessageInfo,
+    modifier: &str,
+    price: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    match price {
+        Some(price) => MODIFIERS.save(deps.storage, modifier, &price)?,
+        None => MODIFIERS.remove(deps.storage, modifier),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "update_modifier")
+        .add_attribute("modifier", modifier)
+        .add_attribute("price", format!("{:?}", price)))
+}
+
+fn set_beverage_category(
+    deps: DepsMut,
+    info: MessageInfo,
+    bev_type: &str,
+    category: Option<String>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut stat = BEVERAGES.load(deps.storage, bev_type)?;
+    stat.category = category;
+    BEVERAGES.save(deps.storage, bev_type, &stat)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_beverage_category")
+        .add_attribute("beverage_type", bev_type)
+        .add_attribute("category", format!("{:?}", stat.category)))
+}
+
+fn set_price_breaks(
+    deps: DepsMut,
+    info: MessageInfo,
+    bev_type: &str,
+    breaks: Vec<PriceBreak>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut min_quantity = 1;
+    for price_break in &breaks {
+        if price_break.min_quantity <= min_quantity {
+            return Err(ContractError::InvalidPriceBreaks {});
+        }
+        price_break.discount.validate()?;
+        min_quantity = price_break.min_quantity;
+    }
+
+    let mut stat = BEVERAGES.load(deps.storage, bev_type)?;
+    stat.price_breaks = breaks;
+    BEVERAGES.save(deps.storage, bev_type, &stat)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_price_breaks")
+        .add_attribute("beverage_type", bev_type)
+        .add_attribute("breaks", stat.price_breaks.len().to_string()))
+}
+
+fn set_loyalty_rule(
+    deps: DepsMut,
+    info: MessageInfo,
+    rule: Option<LoyaltyRule>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if matches!(rule, Some(LoyaltyRule { every: 0, .. })) {
+        return Err(ContractError::InvalidLoyaltyRule {});
+    }
+    LOYALTY_RULE.save(deps.storage, &rule)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_loyalty_rule")
+        .add_attribute("enabled", rule.is_some().to_string()))
+}
+
+fn set_tiers(
+    deps: DepsMut,
+    info: MessageInfo,
+    tiers: Option<TiersConfig>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if let Some(tiers) = &tiers {
+        tiers.validate()?;
+    }
+    TIERS.save(deps.storage, &tiers)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_tiers")
+        .add_attribute("enabled", tiers.is_some().to_string()))
+}
+
+/// Spend of the customer within the spend window.
+fn window_spend(deps: Deps, now: Timestamp, address: &Addr) -> StdResult<Uint128> {
+    let window_start = now.minus_seconds(SPEND_WINDOW_SECONDS);
+    Ok(SPENDINGS
+        .may_load(deps.storage, address)?
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|spending| spending.at > window_start)
+        .map(|spending| spending.amount)
+        .sum())
+}
+
+fn record_spending(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    address: &Addr,
+    amount: Uint128,
+) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    let window_start = now.minus_seconds(SPEND_WINDOW_SECONDS);
+    let mut spendings = SPENDINGS.may_load(storage, address)?.unwrap_or_default();
+    spendings.retain(|spending| spending.at > window_start);
+    spendings.push(Spending { amount, at: now });
+    SPENDINGS.save(storage, address, &spendings)
+}
+
+fn register_promo_code(
+    deps: DepsMut,
+    info: MessageInfo,
+    code_hash: Binary,
+    promo_code: PromoCode,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if code_hash.len() != 32 {
+        return Err(ContractError::InvalidPromoCode {});
+    }
+    promo_code.discount.validate()?;
+    PROMO_CODES.save(deps.storage, &code_hash, &promo_code)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "register_promo_code")
+        .add_attribute("code_hash", code_hash.to_base64()))
+}
+
+fn remove_promo_code(
+    deps: DepsMut,
+    info: MessageInfo,
+    code_hash: Binary,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    PROMO_CODES.remove(deps.storage, &code_hash);
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_promo_code")
+        .add_attribute("code_hash", code_hash.to_base64()))
+}
+
+fn hash_code(code: &str) -> Vec<u8> {
+    Sha256::digest(code.as_bytes()).to_vec()
+}
+
+fn hash_pickup_code(code: &str, salt: &str) -> Vec<u8> {
+    Sha256::digest(&[code.as_bytes(), salt.as_bytes()].concat()).to_vec()
+}
+
+/// What the buyer asks for, shared by `Purchase` and `Quote`.
+struct PurchaseRequest {
+    bev_type: String,
+    quantity: u8,
+    modifiers: Vec<String>,
+    promo_code: Option<String>,
+}
+
+/// The pricing function shared by `Purchase` and `Quote`.
+/// The base price is marked up by the scarcity pricing, discounted by the first
+/// active price rule and topped up with the modifiers surcharge.
+fn quote(
+    deps: Deps,
+    env: &Env,
+    request: &PurchaseRequest,
+    buyer: Option<&Addr>,
+) -> Result<QuoteResponse, ContractError> {
+    let PurchaseRequest {
+        bev_type,
+        quantity,
+        modifiers,
+        promo_code,
+    } = request;
+    let (bev_type, quantity) = (bev_type.as_str(), *quantity);
+    let stat = load_beverage(deps.storage, env.block.time, bev_type)?;
+    let mut price = stat.price;
+    if let Some(pricing) = SCARCITY_PRICING.may_load(deps.storage, bev_type)? {
+        price = pricing.apply(price, stat.amount);
+    }
+
+    // the first active rule wins, so admins control the priority by the order
+    let utc_offset = UTC_OFFSET.load(deps.storage)?;
+    let rule = PRICE_RULES
+        .may_load(deps.storage, bev_type)?
+        .unwrap_or_default()
+        .into_iter()
+        .enumerate()
+        .find(|(_, rule)| rule.is_active(env.block.time, utc_offset));
+    let mut price_rule = None;
+    if let Some((index, rule)) = rule {
+        price = rule.discount.apply(price);
+        price_rule = Some(index as u32);
+    }
+
+    let price_break = stat.price_break(quantity).map(|price_break| {
+        price = price_break.discount.apply(price);
+        price_break.min_quantity
+    });
+
+    let mut modifiers_price = Uint128::zero();
+    for modifier in modifiers {
+        modifiers_price += MODIFIERS
+            .may_load(deps.storage, modifier)?
+            .ok_or_else(|| StdError::not_found(format!("modifier {}", modifier)))?;
+    }
+
+    let mut free_items = 0;
+    if let (Some(rule), Some(buyer)) = (LOYALTY_RULE.load(deps.storage)?, buyer) {
+        if rule.applies(&stat) {
+            let stamps = LOYALTY_CARDS
+                .may_load(deps.storage, buyer)?
+                .unwrap_or_default();
+            free_items = rule.free_items(stamps, quantity);
+        }
+    }
+
+    // the subscription covers the items which are not free anyway
+    let mut subscription_items = 0;
+    if let Some(buyer) = buyer {
+        if let Some(subscription) = SUBSCRIPTIONS.may_load(deps.storage, buyer)? {
+            if subscription.covers(&stat) {
+                let remaining = subscription.remaining(env.block.time, utc_offset);
+                subscription_items = u32::from(quantity - free_items).min(remaining) as u8;
+            }
+        }
+    }
+
+    let paid_items = quantity - free_items - subscription_items;
+    let subtotal = (price + modifiers_price) * Uint128::from(paid_items);
+
+    let mut promo_discount = Uint128::zero();
+    if let Some(code) = promo_code {
+        let code_hash = hash_code(code);
+        let promo = PROMO_CODES
+            .may_load(deps.storage, &code_hash)?
+            .ok_or(ContractError::InvalidPromoCode {})?;
+        let address_uses = match buyer {
+            Some(buyer) => PROMO_CODE_USES
+                .may_load(deps.storage, (&code_hash, buyer))?
+                .unwrap_or_default(),
+            None => 0,
+        };
+        promo.check(bev_type, env.block.time, address_uses)?;
+        promo_discount = subtotal - promo.discount.apply(subtotal);
+    }
+
+    let mut tier_discount = Uint128::zero();
+    if let (Some(tiers), Some(buyer)) = (TIERS.load(deps.storage)?, buyer) {
+        let spent = window_spend(deps, env.block.time, buyer)?;
+        if let Some((_, config)) = tiers.tier(spent) {
+            let discounted = subtotal - promo_discount;
+            tier_discount = discounted
+                - Discount::Percent {
+                    percent: config.discount_percent,
+                }
+                .apply(discounted);
+        }
+    }
+
+    let total = subtotal - promo_discount - tier_discount;
+
+    let mut in_stock = stat.available(env.block.time) >= quantity;
+    for item in RECIPES
+        .may_load(deps.storage, bev_type)?
+        .unwrap_or_default()
+    {
+        let stock = INGREDIENTS
+            .may_load(deps.storage, &item.ingredient)?
+            .unwrap_or_default();
+        in_stock &= stock.amount >= item.amount * Uint128::from(quantity);
+    }
+
+    let enough_balance = buyer
+        .map(|buyer| -> StdResult<_> {
+            let balance = BALANCES.may_load(deps.storage, buyer)?.unwrap_or_default();
+            let limit = CREDIT_LIMITS
+                .may_load(deps.storage, buyer)?
+                .unwrap_or_default();
+            let debt = TABS.may_load(deps.storage, buyer)?.unwrap_or_default();
+            Ok(balance + limit.saturating_sub(debt) >= total)
+        })
+        .transpose()?;
+
+    Ok(QuoteResponse {
+        base_price: stat.price,
+        price,
+        price_rule,
+        price_break,
+        modifiers_price,
+        quantity,
+        free_items,
+        subscription_items,
+        promo_discount,
+        tier_discount,
+        total,
+        in_stock,
+        enough_balance,
+    })
+}
+
+fn purchase(
+    mut deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    request: PurchaseRequest,
+    pay_with_points: Option<Uint128>,
+    pickup_code_hash: Option<Binary>,
+) -> Result<Response, ContractError> {
+    let (bev_type, quantity) = (request.bev_type.as_str(), request.quantity);
+    apply_price_changes(deps.storage, _env.block.time, bev_type)?;
+    let quote = quote(deps.as_ref(), &_env, &request, Some(&info.sender))?;
+    let total = quote.total;
+
+    let mut stamps = 0;
+    if let Some(rule) = LOYALTY_RULE.load(deps.storage)? {
+        let stat = BEVERAGES.load(deps.storage, bev_type)?;
+        if rule.applies(&stat) {
+            stamps = u32::from(quantity);
+            LOYALTY_CARDS.update(deps.storage, &info.sender, |card| -> StdResult<_> {
+                Ok((card.unwrap_or_default() + stamps) % rule.every)
+            })?;
+        }
+    }
+
+    if quote.subscription_items > 0 {
+        let utc_offset = UTC_OFFSET.load(deps.storage)?;
+        SUBSCRIPTIONS.update(deps.storage, &info.sender, |subscription| -> StdResult<_> {
+            let mut subscription = subscription.unwrap();
+            subscription.consume(_env.block.time, utc_offset, quote.subscription_items.into());
+            Ok(subscription)
+        })?;
+    }
+
+    let promo_code_hash = request.promo_code.as_deref().map(hash_code);
+    if let Some(code_hash) = &promo_code_hash {
+        PROMO_CODES.update(deps.storage, code_hash, |promo| -> StdResult<_> {
+            let mut promo = promo.unwrap();
+            promo.uses += 1;
+            Ok(promo)
+        })?;
+        PROMO_CODE_USES.update(
+            deps.storage,
+            (code_hash, &info.sender),
+            |uses| -> StdResult<_> { Ok(uses.unwrap_or_default() + 1) },
+        )?;
+    }
+
+    // points cover at most the whole total, the rest is paid with coins
+    let points = pay_with_points.unwrap_or_default().min(total);
+    redeem_points(deps.storage, _env.block.time, &info.sender, points)?;
+    let paid = total - points;
+
+    let on_tab = take_coins_or_tab(deps.storage, &info.sender, paid)?;
+    let paid = paid - on_tab;
+    let cashback = book_sale(deps.storage, &_env, &info.sender, paid)?;
+    log_purchase(
+        deps.storage,
+        &info.sender,
+        PurchaseRecord {
+            bev_type: bev_type.to_string(),
+            quantity,
+            paid,
+            points,
+            subscription_items: quote.subscription_items,
+            promo_code_hash: promo_code_hash.map(Binary::from),
+            on_tab,
+            cashback,
+            stamps,
+            pickup_code_hash: pickup_code_hash.clone(),
+            purchased_at: _env.block.time,
+        },
+    )?;
+
+    // with a pickup code the items are held in the machine until the code is revealed
+    let events = match &pickup_code_hash {
+        Some(code_hash) => {
+            if code_hash.len() != 32 || PICKUPS.has(deps.storage, code_hash) {
+                return Err(ContractError::InvalidPickupCode {});
+            }
+            if quantity == 0 {
+                return Err(ContractError::ZeroQuantity {});
+            }
+            let mut stat = BEVERAGES.load(deps.storage, bev_type)?;
+            let batches = stat.hold(quantity, _env.block.time)?;
+            BEVERAGES.save(deps.storage, bev_type, &stat)?;
+            let pickup = Pickup {
+                buyer: info.sender.clone(),
+                bev_type: bev_type.to_string(),
+                quantity,
+                paid_at: _env.block.time,
+                batches,
+            };
+            PICKUPS.save(deps.storage, code_hash, &pickup)?;
+            low_stock_event(bev_type, &stat).into_iter().collect()
+        }
+        None => sell_beverage(&mut deps, &_env, bev_type, quantity)?,
+    };
+
+    let mut response = Response::new()
+        .add_attribute("action", "purchase")
+        .add_attribute("beverage_type", bev_type)
+        .add_attribute("price", quote.price)
+        .add_attribute(
+            "price_rule",
+            quote
+                .price_rule
+                .map_or_else(|| "none".to_string(), |index| index.to_string()),
+        )
+        .add_attribute("quantity", quantity.to_string())
+        .add_attribute("free_items", quote.free_items.to_string())
+        .add_attribute("total", total)
+        .add_attribute("paid_with_points", points)
+        .add_attribute("cashback", cashback)
+        .add_attribute("subscription_items", quote.subscription_items.to_string())
+        .add_events(events);
+    if let Some(code_hash) = pickup_code_hash {
+        response = response.add_attribute("pickup_code_hash", code_hash.to_base64());
+    }
+    if !on_tab.is_zero() {
+        response = response.add_attribute("on_tab", on_tab);
+    }
+    Ok(response)
+}
+
+fn take_coins(
+    storage: &mut dyn Storage,
+    address: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    BALANCES.update(
+        storage,
+        address,
+        |balance: Option<Uint128>| -> Result<_, ContractError> {
+            balance
+                .unwrap_or_default()
+                .checked_sub(amount)
+                .or(Err(ContractError::NotEnoughCoins {}))
+        },
+    )?;
+    Ok(())
+}
+
+/// Takes the coins from the balance. The part the balance can't cover is put
+/// on the tab of the address within its credit limit. Returns that part.
+fn take_coins_or_tab(
+    storage: &mut dyn Storage,
+    address: &Addr,
+    amount: Uint128,
+) -> Result<Uint128, ContractError> {
+    let balance = BALANCES.may_load(storage, address)?.unwrap_or_default();
+    let on_tab = amount.saturating_sub(balance);
+    if !on_tab.is_zero() {
+        let limit = CREDIT_LIMITS
+            .may_load(storage, address)?
+            .unwrap_or_default();
+        let debt = TABS.may_load(storage, address)?.unwrap_or_default() + on_tab;
+        if debt > limit {
+            return Err(ContractError::NotEnoughCoins {});
+        }
+        TABS.save(storage, address, &debt)?;
+    }
+
+    take_coins(storage, address, amount - on_tab)?;
+    Ok(on_tab)
+}
+
+/// Moves the coins paid by the buyer to the contract balance and books them as
+/// revenue and spending. Returns the points credited as cashback.
+fn book_sale(
+    storage: &mut dyn Storage,
+    env: &Env,
+    buyer: &Addr,
+    paid: Uint128,
+) -> StdResult<Uint128> {
+    BALANCES.update(
+        storage,
+        &env.contract.address,
+        |balance: Option<Uint128>| -> StdResult<_> { Ok(balance.unwrap() + paid) },
+    )?;
+
+    ON_CHAIN_REVENUE.update(storage, |revenue| -> StdResult<_> { Ok(revenue + paid) })?;
+    record_spending(storage, env.block.time, buyer, paid)?;
+    credit_cashback(storage, env.block.time, buyer, paid)
+}
+
+fn update_bundle(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    name: &str,
+    bundle: Option<Bundle>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    match &bundle {
+        Some(bundle) => {
+            if bundle.items.is_empty() || bundle.items.iter().any(|item| item.quantity == 0) {
+                return Err(ContractError::InvalidBundle {});
+            }
+            let mut components_price = Uint128::zero();
+            for item in &bundle.items {
+                let stat = load_beverage(deps.storage, env.block.time, &item.bev_type)?;
+                components_price += stat.price * Uint128::from(item.quantity);
+            }
+            if bundle.price >= components_price {
+                return Err(ContractError::InvalidBundle {});
+            }
+            BUNDLES.save(deps.storage, name, bundle)?;
+        }
+        None => BUNDLES.remove(deps.storage, name),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "update_bundle")
+        .add_attribute("bundle", name)
+        .add_attribute("price", format!("{:?}", bundle.map(|bundle| bundle.price))))
+}
+
+/// Sells every component of the bundle, the whole purchase fails if any of
+/// them is over.
+fn purchase_bundle(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    name: &str,
+    quantity: u8,
+) -> Result<Response, ContractError> {
+    if quantity == 0 {
+        return Err(ContractError::ZeroQuantity {});
+    }
+
+    let bundle = BUNDLES.load(deps.storage, name)?;
+    let total = bundle.price * Uint128::from(quantity);
+    take_coins(deps.storage, &info.sender, total)?;
+    let cashback = book_sale(deps.storage, &env, &info.sender, total)?;
+
+    let mut events = vec![];
+    for item in &bundle.items {
+        apply_price_changes(deps.storage, env.block.time, &item.bev_type)?;
+        let amount = item
+            .quantity
+            .checked_mul(quantity)
+            .ok_or(ContractError::BeverageIsOver {})?;
+        events.extend(sell_beverage(&mut deps, &env, &item.bev_type, amount)?);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "purchase_bundle")
+        .add_attribute("bundle", name)
+        .add_attribute("quantity", quantity.to_string())
+        .add_attribute("total", total)
+        .add_attribute("cashback", cashback)
+        .add_events(events))
+}
+
+fn update_subscription_plan(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: &str,
+    plan: Option<SubscriptionPlan>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    match &plan {
+        Some(SubscriptionPlan { drinks: 0, .. }) => {
+            return Err(ContractError::InvalidSubscriptionPlan {})
+        }
+        Some(plan) => SUBSCRIPTION_PLANS.save(deps.storage, name, plan)?,
+        None => SUBSCRIPTION_PLANS.remove(deps.storage, name),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "update_subscription_plan")
+        .add_attribute("plan", name)
+        .add_attribute("price", format!("{:?}", plan.map(|plan| plan.price))))
+}
+
+/// Subscribes the sender from now until the end of the `months`-th calendar month.
+fn subscribe(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    name: &str,
+    months: u8,
+) -> Result<Response, ContractError> {
+    if months == 0 {
+        return Err(ContractError::ZeroQuantity {});
+    }
+
+    let now = env.block.time;
+    if let Some(subscription) = SUBSCRIPTIONS.may_load(deps.storage, &info.sender)? {
+        if now < subscription.expires_at {
+            return Err(ContractError::SubscriptionActive {});
+        }
+    }
+
+    let plan = SUBSCRIPTION_PLANS.load(deps.storage, name)?;
+    let total = plan.price * Uint128::from(months);
+    take_coins(deps.storage, &info.sender, total)?;
+    let cashback = book_sale(deps.storage, &env, &info.sender, total)?;
+
+    let utc_offset = UTC_OFFSET.load(deps.storage)?;
+    let month = local_month(now, utc_offset);
+    let subscription = Subscription {
+        plan: name.to_string(),
+        drinks: plan.drinks,
+        categories: plan.categories,
+        started_at: now,
+        expires_at: month_start(month + u32::from(months), utc_offset),
+        month,
+        used: 0,
+    };
+    SUBSCRIPTIONS.save(deps.storage, &info.sender, &subscription)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "subscribe")
+        .add_attribute("plan", name)
+        .add_attribute("expires_at", subscription.expires_at.to_string())
+        .add_attribute("total", total)
+        .add_attribute("cashback", cashback))
+}
+
+fn buy_voucher(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    code_hash: Binary,
+    value: VoucherValue,
+    expires_at: Timestamp,
+) -> Result<Response, ContractError> {
+    if code_hash.len() != 32
+        || expires_at <= env.block.time
+        || VOUCHERS.has(deps.storage, &code_hash)
+    {
+        return Err(ContractError::InvalidVoucher {});
+    }
+
+    let paid = match &value {
+        VoucherValue::Beverage { bev_type } => {
+            let request = PurchaseRequest {
+                bev_type: bev_type.clone(),
+                quantity: 1,
+                modifiers: vec![],
+                promo_code: None,
+            };
+            quote(deps.as_ref(), &env, &request, None)?.total
+        }
+        VoucherValue::Credit { amount } => *amount,
+    };
+    take_coins(deps.storage, &info.sender, paid)?;
+
+    VOUCHERS.save(
+        deps.storage,
+        &code_hash,
+        &Voucher {
+            buyer: info.sender,
+            value,
+            paid,
+            expires_at,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "buy_voucher")
+        .add_attribute("code_hash", code_hash.to_base64())
+        .add_attribute("paid", paid))
+}
+
+/// Gives the drink or the credits of the voucher to the sender.
+fn redeem_voucher(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    code: &str,
+) -> Result<Response, ContractError> {
+    let code_hash = hash_code(code);
+    let voucher = VOUCHERS
+        .may_load(deps.storage, &code_hash)?
+        .ok_or(ContractError::InvalidVoucher {})?;
+    if voucher.expires_at <= env.block.time {
+        return Err(ContractError::VoucherExpired {});
+    }
+    VOUCHERS.remove(deps.storage, &code_hash);
+
+    let mut events = vec![];
+    match &voucher.value {
+        VoucherValue::Beverage { bev_type } => {
+            book_sale(deps.storage, &env, &voucher.buyer, voucher.paid)?;
+            apply_price_changes(deps.storage, env.block.time, bev_type)?;
+            events = sell_beverage(&mut deps, &env, bev_type, 1)?;
+        }
+        VoucherValue::Credit { amount } => {
+            BALANCES.update(deps.storage, &info.sender, |balance| -> StdResult<_> {
+                Ok(balance.unwrap_or_default() + amount)
+            })?;
+        }
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "redeem_voucher")
+        .add_attribute("code_hash", Binary::from(code_hash).to_base64())
+        .add_attribute("redeemer", info.sender)
+        .add_events(events))
+}
+
+/// Returns the coins of an expired voucher to its buyer, anyone can trigger it.
+fn refund_voucher(deps: DepsMut, env: Env, code_hash: Binary) -> Result<Response, ContractError> {
+    let voucher = VOUCHERS
+        .may_load(deps.storage, &code_hash)?
+        .ok_or(ContractError::InvalidVoucher {})?;
+    if env.block.time < voucher.expires_at {
+        return Err(ContractError::VoucherNotExpired {});
+    }
+    VOUCHERS.remove(deps.storage, &code_hash);
+
+    BALANCES.update(deps.storage, &voucher.buyer, |balance| -> StdResult<_> {
+        Ok(balance.unwrap_or_default() + voucher.paid)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "refund_voucher")
+        .add_attribute("code_hash", code_hash.to_base64())
+        .add_attribute("buyer", voucher.buyer)
+        .add_attribute("amount", voucher.paid))
+}
+
+/// Charges the sender at the current price and holds the drinks for the recipient.
+fn gift_drink(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    bev_type: String,
+    recipient: String,
+    quantity: u8,
+) -> Result<Response, ContractError> {
+    let recipient = deps.api.addr_validate(&recipient)?;
+    apply_price_changes(deps.storage, env.block.time, &bev_type)?;
+    let request = PurchaseRequest {
+        bev_type,
+        quantity,
+        modifiers: vec![],
+        promo_code: None,
+    };
+    let total = quote(deps.as_ref(), &env, &request, None)?.total;
+
+    // the gifted items are held so that the recipient can always claim them
+    let mut stat = BEVERAGES.load(deps.storage, &request.bev_type)?;
+    let batches = stat.hold(quantity, env.block.time)?;
+    BEVERAGES.save(deps.storage, &request.bev_type, &stat)?;
+
+    take_coins(deps.storage, &info.sender, total)?;
+    let cashback = book_sale(deps.storage, &env, &info.sender, total)?;
+
+    let id = grant_entitlement(
+        deps.storage,
+        env.block.time,
+        &recipient,
+        info.sender,
+        &request.bev_type,
+        quantity,
+        batches,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "gift_drink")
+        .add_attribute("beverage_type", request.bev_type)
+        .add_attribute("recipient", recipient)
+        .add_attribute("entitlement", id.to_string())
+        .add_attribute("total", total)
+        .add_attribute("cashback", cashback))
+}
+
+fn grant_entitlement(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    holder: &Addr,
+    from: Addr,
+    bev_type: &str,
+    quantity: u8,
+    batches: Vec<Batch>,
+) -> Result<u64, ContractError> {
+    if quantity == 0 {
+        return Err(ContractError::ZeroQuantity {});
+    }
+
+    let id = ENTITLEMENT_COUNT.may_load(storage)?.unwrap_or_default() + 1;
+    ENTITLEMENT_COUNT.save(storage, &id)?;
+    ENTITLEMENTS.save(
+        storage,
+        (holder, id.into()),
+        &Entitlement {
+            id,
+            bev_type: bev_type.to_string(),
+            quantity,
+            from,
+            created_at: now,
+            batches,
+        },
+    )?;
+    Ok(id)
+}
+
+/// Dispenses the drinks of the sender entitlement from the stock.
+fn claim_drink(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let entitlement = ENTITLEMENTS.load(deps.storage, (&info.sender, id.into()))?;
+    ENTITLEMENTS.remove(deps.storage, (&info.sender, id.into()));
+
+    apply_price_changes(deps.storage, env.block.time, &entitlement.bev_type)?;
+    let events = if entitlement.batches.is_empty() {
+        sell_beverage(&mut deps, &env, &entitlement.bev_type, entitlement.quantity)?
+    } else {
+        hand_out_held(
+            &mut deps,
+            &env,
+            &info.sender,
+            &entitlement.bev_type,
+            &entitlement.batches,
+        )?
+    };
+
+    Ok(Response::new()
+        .add_attribute("action", "claim_drink")
+        .add_attribute("entitlement", id.to_string())
+        .add_attribute("beverage_type", entitlement.bev_type)
+        .add_attribute("quantity", entitlement.quantity.to_string())
+        .add_events(events))
+}
+
+/// Escrows the current price of a sold out beverage and queues the buyer for
+/// the next refill.
+fn backorder(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    bev_type: String,
+    quantity: u8,
+) -> Result<Response, ContractError> {
+    if quantity == 0 {
+        return Err(ContractError::ZeroQuantity {});
+    }
+
+    let stat = load_beverage(deps.storage, env.block.time, &bev_type)?;
+    if stat.available(env.block.time) >= quantity {
+        return Err(ContractError::BeverageInStock {});
+    }
+
+    let request = PurchaseRequest {
+        bev_type,
+        quantity,
+        modifiers: vec![],
+        promo_code: None,
+    };
+    let paid = quote(deps.as_ref(), &env, &request, None)?.total;
+    take_coins(deps.storage, &info.sender, paid)?;
+
+    let id = BACKORDER_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+    BACKORDER_COUNT.save(deps.storage, &id)?;
+    BACKORDERS.save(
+        deps.storage,
+        (&request.bev_type, id.into()),
+        &Backorder {
+            id,
+            buyer: info.sender,
+            bev_type: request.bev_type.clone(),
+            quantity,
+            paid,
+            placed_at: env.block.time,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "backorder")
+        .add_attribute("backorder", id.to_string())
+        .add_attribute("beverage_type", request.bev_type)
+        .add_attribute("quantity", quantity.to_string())
+        .add_attribute("paid", paid))
+}
+
+fn cancel_backorder(
+    deps: DepsMut,
+    info: MessageInfo,
+    bev_type: &str,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let backorder = BACKORDERS.load(deps.storage, (bev_type, id.into()))?;
+    if backorder.buyer.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    BACKORDERS.remove(deps.storage, (bev_type, id.into()));
+
+    BALANCES.update(deps.storage, &backorder.buyer, |balance| -> StdResult<_> {
+        Ok(balance.unwrap_or_default() + backorder.paid)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_backorder")
+        .add_attribute("backorder", id.to_string())
+        .add_attribute("refund", backorder.paid))
+}
+
+fn set_reservation_blocks(
+    deps: DepsMut,
+    info: MessageInfo,
+    blocks: u64,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    RESERVATION_BLOCKS.save(deps.storage, &blocks)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_reservation_blocks")
+        .add_attribute("blocks", blocks.to_string()))
+}
+
+/// Escrows the current price and takes the items out of the stock until the
+/// reservation is collected or expires.
+fn reserve(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    bev_type: String,
+    quantity: u8,
+) -> Result<Response, ContractError> {
+    if quantity == 0 {
+        return Err(ContractError::ZeroQuantity {});
+    }
+
+    apply_price_changes(deps.storage, env.block.time, &bev_type)?;
+    let request = PurchaseRequest {
+        bev_type,
+        quantity,
+        modifiers: vec![],
+        promo_code: None,
+    };
+    let paid = quote(deps.as_ref(), &env, &request, None)?.total;
+    take_coins(deps.storage, &info.sender, paid)?;
+
+    let bev_type = request.bev_type;
+    let mut stat = BEVERAGES.load(deps.storage, &bev_type)?;
+    let batches = stat.hold(quantity, env.block.time)?;
+    BEVERAGES.save(deps.storage, &bev_type, &stat)?;
+
+    let id = RESERVATION_COUNT
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        + 1;
+    RESERVATION_COUNT.save(deps.storage, &id)?;
+    let expires_at_height = env.block.height + RESERVATION_BLOCKS.load(deps.storage)?;
+    RESERVATIONS.save(
+        deps.storage,
+        id.into(),
+        &Reservation {
+            id,
+            owner: info.sender,
+            bev_type: bev_type.clone(),
+            quantity,
+            paid,
+            batches,
+            expires_at_height,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "reserve")
+        .add_attribute("reservation", id.to_string())
+        .add_attribute("beverage_type", &bev_type)
+        .add_attribute("quantity", quantity.to_string())
+        .add_attribute("paid", paid)
+        .add_attribute("expires_at_height", expires_at_height.to_string())
+        .add_events(low_stock_event(&bev_type, &stat)))
+}
+
+/// Completes the sale of a reservation of the sender.
+fn collect(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let reservation = RESERVATIONS.load(deps.storage, id.into())?;
+    if reservation.owner.ne(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    if env.block.height >= reservation.expires_at_height {
+        return Err(ContractError::ReservationExpired {});
+    }
+    if reservation.has_expired_items(env.block.time) {
+        return Err(ContractError::BeverageExpired {});
+    }
+    RESERVATIONS.remove(deps.storage, id.into());
+
+    let mut stat = BEVERAGES.load(deps.storage, &reservation.bev_type)?;
+    stat.dispense_held(reservation.quantity);
+    BEVERAGES.save(deps.storage, &reservation.bev_type, &stat)?;
+
+    let cashback = book_sale(deps.storage, &env, &info.sender, reservation.paid)?;
+    let events = consume_ingredients(&mut deps, &reservation.bev_type, reservation.quantity)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "collect")
+        .add_attribute("reservation", id.to_string())
+        .add_attribute("beverage_type", reservation.bev_type)
+        .add_attribute("quantity", reservation.quantity.to_string())
+        .add_attribute("cashback", cashback)
+        .add_events(events))
+}
+
+/// Returns the stock and the coins of up to `limit` stale reservations,
+/// anyone can trigger it. A reservation holding items which went off is stale
+/// as well, since it can no longer be collected.
+fn expire_reservations(
+    deps: DepsMut,
+    env: Env,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let expired: Vec<_> = RESERVATIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| match item {
+            Ok((_, reservation)) => {
+                env.block.height >= reservation.expires_at_height
+                    || reservation.has_expired_items(env.block.time)
+            }
+            Err(_) => true,
+        })
+        .take(limit)
+        .map(|item| item.map(|(_, reservation)| reservation))
+        .collect::<StdResult<_>>()?;
+
+    for reservation in &expired {
+        RESERVATIONS.remove(deps.storage, reservation.id.into());
+        let mut stat = BEVERAGES.load(deps.storage, &reservation.bev_type)?;
+        stat.release(reservation.batches.clone());
+        BEVERAGES.save(deps.storage, &reservation.bev_type, &stat)?;
+        BALANCES.update(
+            deps.storage,
+            &reservation.owner,
+            |balance| -> StdResult<_> { Ok(balance.unwrap_or_default() + reservation.paid) },
+        )?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "expire_reservations")
+        .add_attribute("expired", expired.len().to_string()))
+}
+
+/// Pays for an order which the baristas prepare in the order of placement.
+fn place_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    bev_type: String,
+    quantity: u8,
+    modifiers: Vec<String>,
+) -> Result<Response, ContractError> {
+    if quantity == 0 {
+        return Err(ContractError::ZeroQuantity {});
+    }
+
+    apply_price_changes(deps.storage, env.block.time, &bev_type)?;
+    let request = PurchaseRequest {
+        bev_type,
+        quantity,
+        modifiers,
+        promo_code: None,
+    };
+    let paid = quote(deps.as_ref(), &env, &request, None)?.total;
+    take_coins(deps.storage, &info.sender, paid)?;
+
+    let id = BARISTA_ORDER_COUNT
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        + 1;
+    BARISTA_ORDER_COUNT.save(deps.storage, &id)?;
+    BARISTA_ORDERS.save(
+        deps.storage,
+        id.into(),
+        &BaristaOrder {
+            id,
+            customer: info.sender,
+            bev_type: request.bev_type.clone(),
+            quantity,
+            modifiers: request.modifiers,
+            paid,
+            status: OrderStatus::Placed,
+            placed_at: env.block.time,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "place_order")
+        .add_attribute("order", id.to_string())
+        .add_attribute("beverage_type", request.bev_type)
+        .add_attribute("quantity", quantity.to_string())
+        .add_attribute("paid", paid))
+}
+
+/// Moves the order to the next status. The sale is completed when the barista
+/// starts preparing the order.
+fn advance_order(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    ensure_operator(deps.as_ref(), &info.sender)?;
+
+    let mut order = BARISTA_ORDERS.load(deps.storage, id.into())?;
+    let mut events = vec![];
+    order.status = match order.status {
+        OrderStatus::Placed => {
+            book_sale(deps.storage, &env, &order.customer, order.paid)?;
+            apply_price_changes(deps.storage, env.block.time, &order.bev_type)?;
+            events = sell_beverage(&mut deps, &env, &order.bev_type, order.quantity)?;
+            OrderStatus::Preparing
+        }
+        OrderStatus::Preparing => OrderStatus::Ready,
+        OrderStatus::Ready => OrderStatus::Collected,
+        status => {
+            return Err(ContractError::InvalidOrderStatus {
+                status: format!("{:?}", status),
+            })
+        }
+    };
+
+    if order.status == OrderStatus::Collected {
+        BARISTA_ORDERS.remove(deps.storage, id.into());
+    } else {
+        BARISTA_ORDERS.save(deps.storage, id.into(), &order)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "advance_order")
+        .add_attribute("order", id.to_string())
+        .add_attribute("status", format!("{:?}", order.status))
+        .add_events(events))
+}
+
+/// Cancels an order which is not being prepared yet and refunds the customer.
+fn cancel_order(deps: DepsMut, info: MessageInfo, id: u64) -> Result<Response, ContractError> {
+    let order = BARISTA_ORDERS.load(deps.storage, id.into())?;
+    if order.customer.ne(&info.sender) {
+        ensure_operator(deps.as_ref(), &info.sender)?;
+    }
+    if order.status != OrderStatus::Placed {
+        return Err(ContractError::InvalidOrderStatus {
+            status: format!("{:?}", order.status),
+        });
+    }
+    BARISTA_ORDERS.remove(deps.storage, id.into());
+
+    BALANCES.update(deps.storage, &order.customer, |balance| -> StdResult<_> {
+        Ok(balance.unwrap_or_default() + order.paid)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_order")
+        .add_attribute("order", id.to_string())
+        .add_attribute("status", format!("{:?}", OrderStatus::Cancelled))
+        .add_attribute("refund", order.paid))
+}
+
+/// Releases a purchase made with a pickup code once the code and its salt
+/// are revealed.
+fn collect_pickup(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    code: &str,
+    salt: &str,
+) -> Result<Response, ContractError> {
+    ensure_operator(deps.as_ref(), &info.sender)?;
+
+    if salt.len() < MIN_PICKUP_SALT_LENGTH {
+        return Err(ContractError::InvalidPickupCode {});
+    }
+    let code_hash = hash_pickup_code(code, salt);
+    let pickup = PICKUPS
+        .may_load(deps.storage, &code_hash)?
+        .ok_or(ContractError::InvalidPickupCode {})?;
+    PICKUPS.remove(deps.storage, &code_hash);
+
+    apply_price_changes(deps.storage, env.block.time, &pickup.bev_type)?;
+    let events = hand_out_held(
+        &mut deps,
+        &env,
+        &info.sender,
+        &pickup.bev_type,
+        &pickup.batches,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "collect_pickup")
+        .add_attribute("buyer", pickup.buyer)
+        .add_attribute("beverage_type", pickup.bev_type)
+        .add_attribute("quantity", pickup.quantity.to_string())
+        .add_events(events))
+}
+
+fn log_purchase(storage: &mut dyn Storage, buyer: &Addr, record: PurchaseRecord) -> StdResult<()> {
+    let window_start = record
+        .purchased_at
+        .minus_seconds(REFUND_WINDOW.load(storage)?);
+    let mut purchases = PURCHASES.may_load(storage, buyer)?.unwrap_or_default();
+    purchases.retain(|purchase| purchase.purchased_at > window_start);
+    purchases.push(record);
+    PURCHASES.save(storage, buyer, &purchases)
+}
+
+fn set_refund_window(
+    deps: DepsMut,
+    info: MessageInfo,
+    seconds: u64,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    REFUND_WINDOW.save(deps.storage, &seconds)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_refund_window")
+        .add_attribute("seconds", seconds.to_string()))
+}
+
+fn set_arbiter(
+    deps: DepsMut,
+    info: MessageInfo,
+    arbiter: Option<String>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let arbiter = arbiter
+        .map(|arbiter| deps.api.addr_validate(&arbiter))
+        .transpose()?;
+    ARBITER.save(deps.storage, &arbiter)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_arbiter")
+        .add_attribute("arbiter", format!("{:?}", arbiter)))
+}
+
+/// Opens a dispute for a logged purchase of the sender.
+fn request_refund(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    bev_type: &str,
+    purchased_at: Timestamp,
+    reason: String,
+) -> Result<Response, ContractError> {
+    let window = REFUND_WINDOW.load(deps.storage)?;
+    if env.block.time >= purchased_at.plus_seconds(window) {
+        return Err(ContractError::RefundWindowClosed {});
+    }
+
+    let mut purchases = PURCHASES
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+    let index = purchases
+        .iter()
+        .position(|purchase| purchase.bev_type == bev_type && purchase.purchased_at == purchased_at)
+        .ok_or(ContractError::PurchaseNotFound {})?;
+    // a purchase can be disputed only once
+    let purchase = purchases.remove(index);
+    PURCHASES.save(deps.storage, &info.sender, &purchases)?;
+
+    let id = DISPUTE_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+    DISPUTE_COUNT.save(deps.storage, &id)?;
+    DISPUTES.save(
+        deps.storage,
+        id.into(),
+        &Dispute {
+            id,
+            buyer: info.sender,
+            purchase,
+            reason,
+            opened_at: env.block.time,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "request_refund")
+        .add_attribute("dispute", id.to_string())
+        .add_attribute("beverage_type", bev_type))
+}
+
+/// Closes a dispute. An approved refund reverses the purchase: the coins and
+/// the settled part of the tab are paid back from the contract balance, the
+/// rest of the tab is cancelled, the points, subscription drinks and promo code
+/// use are given back and the cashback, spend and loyalty stamps are taken
+/// back. Items of an open pickup are released, other undispensed items can be
+/// put back into the stock.
+fn resolve_refund(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+    approve: bool,
+    restock: bool,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    let arbiter = ARBITER.load(deps.storage)?;
+    if info.sender.ne(&admin) && arbiter.as_ref() != Some(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let dispute = DISPUTES.load(deps.storage, id.into())?;
+    DISPUTES.remove(deps.storage, id.into());
+
+    let (buyer, purchase) = (&dispute.buyer, &dispute.purchase);
+    let mut refund = Uint128::zero();
+    if approve {
+        let debt = TABS.may_load(deps.storage, buyer)?.unwrap_or_default();
+        let cancelled = purchase.on_tab.min(debt);
+        if cancelled == debt {
+            TABS.remove(deps.storage, buyer);
+        } else {
+            TABS.save(deps.storage, buyer, &(debt - cancelled))?;
+        }
+        refund = purchase.paid + purchase.on_tab - cancelled;
+
+        // cashback which is spent or expired already is kept from the refund
+        let points: Uint128 = POINTS
+            .may_load(deps.storage, buyer)?
+            .unwrap_or_default()
+            .iter()
+            .filter(|grant| !grant.is_expired(env.block.time))
+            .map(|grant| grant.amount)
+            .sum();
+        let clawed_back = purchase.cashback.min(points);
+        redeem_points(deps.storage, env.block.time, buyer, clawed_back)?;
+        refund = refund.saturating_sub(purchase.cashback - clawed_back);
+        grant_points(deps.storage, env.block.time, buyer, purchase.points)?;
+
+        if purchase.subscription_items > 0 {
+            let utc_offset = UTC_OFFSET.load(deps.storage)?;
+            if let Some(mut subscription) = SUBSCRIPTIONS.may_load(deps.storage, buyer)? {
+                // the allowance of a past month is reset already
+                if subscription.month == local_month(purchase.purchased_at, utc_offset) {
+                    subscription.used = subscription
+                        .used
+                        .saturating_sub(purchase.subscription_items.into());
+                    SUBSCRIPTIONS.save(deps.storage, buyer, &subscription)?;
+                }
+            }
+        }
+        if let Some(code_hash) = &purchase.promo_code_hash {
+            if let Some(mut promo) = PROMO_CODES.may_load(deps.storage, code_hash)? {
+                promo.uses = promo.uses.saturating_sub(1);
+                PROMO_CODES.save(deps.storage, code_hash, &promo)?;
+            }
+            if let Some(uses) = PROMO_CODE_USES.may_load(deps.storage, (code_hash, buyer))? {
+                PROMO_CODE_USES.save(deps.storage, (code_hash, buyer), &uses.saturating_sub(1))?;
+            }
+        }
+
+        if let Some(mut spendings) = SPENDINGS.may_load(deps.storage, buyer)? {
+            if let Some(index) = spendings.iter().position(|spending| {
+                spending.amount == purchase.paid && spending.at == purchase.purchased_at
+            }) {
+                spendings.remove(index);
+                SPENDINGS.save(deps.storage, buyer, &spendings)?;
+            }
+        }
+        if let Some(rule) = LOYALTY_RULE.load(deps.storage)? {
+            if purchase.stamps > 0 {
+                LOYALTY_CARDS.update(deps.storage, buyer, |card| -> StdResult<_> {
+                    let card = card.unwrap_or_default();
+                    Ok((card + rule.every - purchase.stamps % rule.every) % rule.every)
+                })?;
+            }
+        }
+
+        take_coins(deps.storage, &env.contract.address, refund)?;
+        BALANCES.update(deps.storage, buyer, |balance| -> StdResult<_> {
+            Ok(balance.unwrap_or_default() + refund)
+        })?;
+        ON_CHAIN_REVENUE.update(deps.storage, |revenue| -> StdResult<_> {
+            Ok(revenue.saturating_sub(refund))
+        })?;
+
+        let pickup = match &purchase.pickup_code_hash {
+            Some(code_hash) => PICKUPS
+                .may_load(deps.storage, code_hash)?
+                .filter(|pickup| pickup.buyer.eq(buyer) && pickup.paid_at == purchase.purchased_at)
+                .map(|pickup| (code_hash, pickup)),
+            None => None,
+        };
+        if let Some((code_hash, pickup)) = pickup {
+            PICKUPS.remove(deps.storage, code_hash);
+            let mut stat = BEVERAGES.load(deps.storage, &purchase.bev_type)?;
+            stat.release(pickup.batches);
+            BEVERAGES.save(deps.storage, &purchase.bev_type, &stat)?;
+        } else if restock {
+            BEVERAGES.update(
+                deps.storage,
+                &purchase.bev_type,
+                |stat| -> Result<_, ContractError> {
+                    stat.unwrap_or_default()
+                        .refill(purchase.quantity, env.block.time, None)
+                },
+            )?;
+        }
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "resolve_refund")
+        .add_attribute("dispute", id.to_string())
+        .add_attribute("approve", approve.to_string())
+        .add_attribute("refund", refund))
+}
+
+fn set_credit_limit(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+    limit: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let address = deps.api.addr_validate(&address)?;
+    match limit {
+        Some(limit) => CREDIT_LIMITS.save(deps.storage, &address, &limit)?,
+        None => CREDIT_LIMITS.remove(deps.storage, &address),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_credit_limit")
+        .add_attribute("address", address)
+        .add_attribute("limit", format!("{:?}", limit)))
+}
+
+/// Repays the tab of the sender, the whole debt by default.
+fn settle_tab(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let debt = TABS
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+    let amount = amount.unwrap_or(debt).min(debt);
+    take_coins(deps.storage, &info.sender, amount)?;
+    let cashback = book_sale(deps.storage, &env, &info.sender, amount)?;
+
+    let debt = debt - amount;
+    if debt.is_zero() {
+        TABS.remove(deps.storage, &info.sender);
+    } else {
+        TABS.save(deps.storage, &info.sender, &debt)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "settle_tab")
+        .add_attribute("amount", amount)
+        .add_attribute("debt", debt)
+        .add_attribute("cashback", cashback))
+}
+
+/// Starts a purchase for the initiator which the participants pay for.
+/// The shares must add up to the current total, which is kept for the order.
+fn create_group_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    request: PurchaseRequest,
+    shares: Vec<ShareRequest>,
+    expires_at: Timestamp,
+) -> Result<Response, ContractError> {
+    if expires_at <= env.block.time {
+        return Err(ContractError::GroupOrderExpired {});
+    }
+
+    if request.quantity == 0 {
+        return Err(ContractError::ZeroQuantity {});
+    }
+
+    apply_price_changes(deps.storage, env.block.time, &request.bev_type)?;
+    // the sale belongs to the participants, so no perks of the initiator apply
+    let quote = quote(deps.as_ref(), &env, &request, None)?;
+    let total = quote.total;
+    let shares = shares
+        .into_iter()
+        .map(|share| -> StdResult<_> {
+            Ok(GroupShare {
+                address: deps.api.addr_validate(&share.address)?,
+                amount: share.amount,
+                funded: false,
+            })
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+    if shares.iter().map(|share| share.amount).sum::<Uint128>() != total {
+        return Err(ContractError::InvalidShares {});
+    }
+
+    let id = GROUP_ORDER_COUNT
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        + 1;
+    GROUP_ORDER_COUNT.save(deps.storage, &id)?;
+    GROUP_ORDERS.save(
+        deps.storage,
+        id.into(),
+        &GroupOrder {
+            id,
+            initiator: info.sender,
+            bev_type: request.bev_type.clone(),
+            quantity: request.quantity,
+            modifiers: request.modifiers,
+            price: quote.price,
+            total,
+            shares,
+            expires_at,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "create_group_order")
+        .add_attribute("group_order", id.to_string())
+        .add_attribute("beverage_type", request.bev_type)
+        .add_attribute("total", total))
+}
+
+/// Pays the share of the sender. The last share sells the items at the quoted
+/// total, each participant is credited the spend and cashback of their share.
+/// Group orders are not logged for refunds.
+fn fund_group_order(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let mut order = GROUP_ORDERS.load(deps.storage, id.into())?;
+    if env.block.time >= order.expires_at {
+        return Err(ContractError::GroupOrderExpired {});
+    }
+
+    let index = order
+        .shares
+        .iter()
+        .position(|share| share.address == info.sender && !share.funded);
+    let share = match index {
+        Some(index) => &mut order.shares[index],
+        None if order
+            .shares
+            .iter()
+            .any(|share| share.address == info.sender) =>
+        {
+            return Err(ContractError::ShareAlreadyFunded {})
+        }
+        None => return Err(ContractError::Unauthorized {}),
+    };
+    take_coins(deps.storage, &info.sender, share.amount)?;
+    share.funded = true;
+
+    if order.shares.iter().any(|share| !share.funded) {
+        GROUP_ORDERS.save(deps.storage, id.into(), &order)?;
+        return Ok(Response::new()
+            .add_attribute("action", "fund_group_order")
+            .add_attribute("group_order", id.to_string()));
+    }
+
+    GROUP_ORDERS.remove(deps.storage, id.into());
+    apply_price_changes(deps.storage, env.block.time, &order.bev_type)?;
+    let events = sell_beverage(&mut deps, &env, &order.bev_type, order.quantity)?;
+    let mut cashback = Uint128::zero();
+    for share in &order.shares {
+        cashback += book_sale(deps.storage, &env, &share.address, share.amount)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "fund_group_order")
+        .add_attribute("group_order", id.to_string())
+        .add_attribute("initiator", order.initiator)
+        .add_attribute("beverage_type", order.bev_type)
+        .add_attribute("price", order.price)
+        .add_attribute("quantity", order.quantity.to_string())
+        .add_attribute("total", order.total)
+        .add_attribute("cashback", cashback)
+        .add_events(events))
+}
+
+/// Refunds the funded shares of an expired group order, anyone can trigger it.
+fn expire_group_order(deps: DepsMut, env: Env, id: u64) -> Result<Response, ContractError> {
+    let order = GROUP_ORDERS.load(deps.storage, id.into())?;
+    if env.block.time < order.expires_at {
+        return Err(ContractError::GroupOrderNotExpired {});
+    }
+    GROUP_ORDERS.remove(deps.storage, id.into());
+
+    for share in order.shares.iter().filter(|share| share.funded) {
+        BALANCES.update(deps.storage, &share.address, |balance| -> StdResult<_> {
+            Ok(balance.unwrap_or_default() + share.amount)
+        })?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "expire_group_order")
+        .add_attribute("group_order", id.to_string()))
+}
+
+fn set_points_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    config: Option<PointsConfig>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if matches!(config, Some(PointsConfig { cashback_percent, .. }) if cashback_percent > 100) {
+        return Err(ContractError::InvalidPointsConfig {});
+    }
+    POINTS_CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_points_config")
+        .add_attribute("enabled", config.is_some().to_string()))
+}
+
+/// Credits the configured share of the coins paid as points.
+fn credit_cashback(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    address: &Addr,
+    paid: Uint128,
+) -> StdResult<Uint128> {
+    let config = match POINTS_CONFIG.load(storage)? {
+        Some(config) => config,
+        None => return Ok(Uint128::zero()),
+    };
+
+    let amount = paid.multiply_ratio(config.cashback_percent, 100_u8);
+    grant_points(storage, now, address, amount)?;
+    Ok(amount)
+}
+
+/// Credits points which expire as configured.
+fn grant_points(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    address: &Addr,
+    amount: Uint128,
+) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    let expires_after = POINTS_CONFIG
+        .load(storage)?
+        .and_then(|config| config.expires_after);
+    let mut grants = POINTS.may_load(storage, address)?.unwrap_or_default();
+    grants.push(PointsGrant {
+        amount,
+        expires_at: expires_after.map(|seconds| now.plus_seconds(seconds)),
+    });
+    POINTS.save(storage, address, &grants)
+}
+
+/// Spends points starting from the grants which expire first.
+fn redeem_points(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    address: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    let mut grants: Vec<_> = POINTS
+        .may_load(storage, address)?
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|grant| !grant.is_expired(now))
+        .collect();
+    grants.sort_by_key(|grant| grant.expires_at.map_or(u64::MAX, |at| at.nanos()));
+
+    let mut left = amount;
+    for grant in grants.iter_mut() {
+        let taken = left.min(grant.amount);
+        grant.amount -= taken;
+        left -= taken;
+    }
+    if !left.is_zero() {
+        return Err(ContractError::NotEnoughPoints {});
+    }
+
+    grants.retain(|grant| !grant.amount.is_zero());
+    POINTS.save(storage, address, &grants)?;
+    Ok(())
+}
+
+fn record_external_sale(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    bev_type: &str,
+    quantity: u8,
+    amount: Uint128,
+    method: &str,
+) -> Result<Response, ContractError> {
+    ensure_operator(deps.as_ref(), &info.sender)?;
+
+    let events = sell_beverage(&mut deps, &env, bev_type, quantity)?;
+    OFF_CHAIN_REVENUE.update(
+        deps.storage,
+        method,
+        |revenue: Option<Uint128>| -> StdResult<_> { Ok(revenue.unwrap_or_default() + amount) },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "record_external_sale")
+        .add_attribute("beverage_type", bev_type)
+        .add_attribute("quantity", quantity.to_string())
+        .add_attribute("amount", amount)
+        .add_attribute("method", method)
+        .add_events(events))
+}
+
+fn update_operators(
+    deps: DepsMut,
+    info: MessageInfo,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    for address in &add {
+        let address = deps.api.addr_validate(address)?;
+        OPERATORS.save(deps.storage, &address, &Empty {})?;
+    }
+    for address in &remove {
+        let address = deps.api.addr_validate(address)?;
+        OPERATORS.remove(deps.storage, &address);
+    }
+
+    Ok(Response::new().add_attribute("action", "update_operators"))
+}
+
+/// Operators run the counter on behalf of the admin, so the admin is an operator too.
+fn ensure_operator(deps: Deps, sender: &Addr) -> Result<(), ContractError> {
+    if sender.eq(&ADMIN.load(deps.storage)?) || OPERATORS.has(deps.storage, sender) {
+        Ok(())
+    } else {
+        Err(ContractError::Unauthorized {})
+    }
+}
+
+fn withdraw_income(deps: DepsMut, _env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender.ne(&admin) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let cur_contract_balance = BALANCES.load(deps.storage, &_env.contract.address)?;
+
+    BALANCES.update(
+        deps.storage,
+        &info.sender,
+        |balance: Option<Uint128>| -> StdResult<_> {
+            Ok(balance.unwrap_or_default() + cur_contract_balance)
+        },
+    )?;
+
+    BALANCES.update(
+        deps.storage,
+        &_env.contract.address,
+        |balance: Option<Uint128>| -> StdResult<_> {
+            Ok(balance.unwrap_or_default() - cur_contract_balance)
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "winthdraw_income")
+        .add_attribute("amount", cur_contract_balance)
+        .add_attribute("recipient", admin))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Balance { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            let balance = BALANCES
+                .may_load(deps.storage, &address)?
+                .unwrap_or_default();
+            Ok(to_binary(&cw20::BalanceResponse { balance })?)
+        }
+        QueryMsg::BeverageStat { bev_type } => {
+            let bev_stat = load_beverage(deps.storage, env.block.time, &bev_type)?;
+            Ok(to_binary(&bev_stat)?)
+        }
+        QueryMsg::IngredientStat { ingredient } => {
+            let stat = INGREDIENTS.load(deps.storage, &ingredient)?;
+            Ok(to_binary(&stat)?)
+        }
+        QueryMsg::Recipe { bev_type } => {
+            let recipe = RECIPES
+                .may_load(deps.storage, &bev_type)?
+                .unwrap_or_default();
+            Ok(to_binary(&RecipeResponse { recipe })?)
+        }
+        QueryMsg::Menu {} => to_binary(&query_menu(deps, env)?),
+        QueryMsg::RestockReport {} => to_binary(&query_restock_report(deps)?),
+        QueryMsg::PriceRules { bev_type } => {
+            let rules = PRICE_RULES
+                .may_load(deps.storage, &bev_type)?
+                .unwrap_or_default();
+            let utc_offset = UTC_OFFSET.load(deps.storage)?;
+            Ok(to_binary(&PriceRulesResponse { rules, utc_offset })?)
+        }
+        QueryMsg::Quote {
+            bev_type,
+            quantity,
+            modifiers,
+            buyer,
+            promo_code,
+        } => {
+            let buyer = buyer
+                .map(|buyer| deps.api.addr_validate(&buyer))
+                .transpose()?;
+            let request = PurchaseRequest {
+                bev_type,
+                quantity,
+                modifiers,
+                promo_code,
+            };
+            let quote = quote(deps, &env, &request, buyer.as_ref()).map_err(into_std_error)?;
+            to_binary(&quote)
+        }
+        QueryMsg::ScheduledPriceChanges { bev_type } => {
+            let changes = PRICE_CHANGES
+                .may_load(deps.storage, &bev_type)?
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|change| change.effective_at > env.block.time)
+                .collect();
+            Ok(to_binary(&ScheduledPriceChangesResponse { changes })?)
+        }
+        QueryMsg::LoyaltyStatus { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            let stamps = LOYALTY_CARDS
+                .may_load(deps.storage, &address)?
+                .unwrap_or_default();
+            let rule = LOYALTY_RULE.load(deps.storage)?;
+            Ok(to_binary(&LoyaltyStatusResponse {
+                stamps,
+                remaining: rule.as_ref().map(|rule| rule.every - stamps % rule.every),
+                rule,
+            })?)
+        }
+        QueryMsg::Tier { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            to_binary(&query_tier(deps, &env, &address)?)
+        }
+        QueryMsg::SubscriptionPlans {} => {
+            let plans = SUBSCRIPTION_PLANS
+                .range(deps.storage, None, None, Order::Ascending)
+                .map(|item| {
+                    let (name, plan) = item?;
+                    Ok(SubscriptionPlanInfo {
+                        name: String::from_utf8(name)?,
+                        plan,
+                    })
+                })
+                .collect::<StdResult<_>>()?;
+            Ok(to_binary(&SubscriptionPlansResponse { plans })?)
+        }
+        QueryMsg::Subscription { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            let subscription = SUBSCRIPTIONS.may_load(deps.storage, &address)?;
+            let utc_offset = UTC_OFFSET.load(deps.storage)?;
+            let remaining = subscription.as_ref().map_or(0, |subscription| {
+                subscription.remaining(env.block.time, utc_offset)
+            });
+            Ok(to_binary(&SubscriptionResponse {
+                subscription,
+                remaining,
+            })?)
+        }
+        QueryMsg::Pickup { code_hash } => {
+            let pickup = PICKUPS.load(deps.storage, &code_hash)?;
+            Ok(to_binary(&pickup)?)
+        }
+        QueryMsg::Voucher { code_hash } => {
+            let voucher = VOUCHERS.load(deps.storage, &code_hash)?;
+            Ok(to_binary(&voucher)?)
+        }
+        QueryMsg::Entitlements {
+            address,
+            start_after,
+            limit,
+        } => to_binary(&query_entitlements(deps, address, start_after, limit)?),
+        QueryMsg::Reservations {
+            owner,
+            start_after,
+            limit,
+        } => to_binary(&query_reservations(deps, owner, start_after, limit)?),
+        QueryMsg::OrderQueue { start_after, limit } => {
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let start = start_after.map(|id| Bound::exclusive(U64Key::from(id)));
+            let orders = BARISTA_ORDERS
+                .range(deps.storage, start, None, Order::Ascending)
+                .take(limit)
+                .map(|item| item.map(|(_, order)| order))
+                .collect::<StdResult<_>>()?;
+            Ok(to_binary(&OrdersResponse { orders })?)
+        }
+        QueryMsg::CustomerOrders { customer } => {
+            let customer = deps.api.addr_validate(&customer)?;
+            let orders = BARISTA_ORDERS
+                .range(deps.storage, None, None, Order::Ascending)
+                .filter(|item| match item {
+                    Ok((_, order)) => order.customer.eq(&customer),
+                    Err(_) => true,
+                })
+                .map(|item| item.map(|(_, order)| order))
+                .collect::<StdResult<_>>()?;
+            Ok(to_binary(&OrdersResponse { orders })?)
+        }
+        QueryMsg::Backorders { bev_type } => {
+            let backorders = BACKORDERS
+                .prefix(&bev_type)
+                .range(deps.storage, None, None, Order::Ascending)
+                .map(|item| item.map(|(_, backorder)| backorder))
+                .collect::<StdResult<_>>()?;
+            Ok(to_binary(&BackordersResponse { backorders })?)
+        }
+        QueryMsg::Disputes { start_after, limit } => {
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+            let start = start_after.map(|id| Bound::exclusive(U64Key::from(id)));
+            let disputes = DISPUTES
+                .range(deps.storage, start, None, Order::Ascending)
+                .take(limit)
+                .map(|item| item.map(|(_, dispute)| dispute))
+                .collect::<StdResult<_>>()?;
+            Ok(to_binary(&DisputesResponse { disputes })?)
+        }
+        QueryMsg::Tabs { start_after, limit } => to_binary(&query_tabs(deps, start_after, limit)?),
+        QueryMsg::GroupOrder { id } => {
+            let order = GROUP_ORDERS.load(deps.storage, id.into())?;
+            Ok(to_binary(&order)?)
+        }
+        QueryMsg::Points { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            let grants: Vec<_> = POINTS
+                .may_load(deps.storage, &address)?
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|grant| !grant.is_expired(env.block.time))
+                .collect();
+            Ok(to_binary(&PointsResponse {
+                balance: grants.iter().map(|grant| grant.amount).sum(),
+                grants,
+            })?)
+        }
+        QueryMsg::Revenue {} => to_binary(&query_revenue(deps)?),
+        QueryMsg::InventoryLedger {
+            bev_type,
+            start_after,
+            limit,
+        } => to_binary(&query_inventory_ledger(deps, bev_type, start_after, limit)?),
+        QueryMsg::Batches { bev_type } => {
+            let stat = BEVERAGES.load(deps.storage, &bev_type)?;
+            Ok(to_binary(&BatchesResponse {
+                expired: stat.expired(env.block.time),
+                batches: stat.batches,
+            })?)
+        }
+    }
+}
+
+/// Queries share the execution logic which fails with `ContractError`.
+fn into_std_error(err: ContractError) -> StdError {
+    match err {
+        ContractError::Std(err) => err,
+        err => StdError::generic_err(err.to_string()),
+    }
+}
+
+fn query_tier(deps: Deps, env: &Env, address: &Addr) -> StdResult<TierResponse> {
+    let spent = window_spend(deps, env.block.time, address)?;
+    let tiers = match TIERS.load(deps.storage)? {
+        Some(tiers) => tiers,
+        None => {
+            return Ok(TierResponse {
+                tier: None,
+                spent,
+                next_tier: None,
+                amount_to_next_tier: None,
+            })
+        }
+    };
+
+    let next = tiers.tiers().find(|(_, config)| spent < config.min_spend);
+    Ok(TierResponse {
+        tier: tiers.tier(spent).map(|(tier, _)| tier),
+        spent,
+        next_tier: next.map(|(tier, _)| tier),
+        amount_to_next_tier: next.map(|(_, config)| config.min_spend - spent),
+    })
+}
+
+fn query_restock_report(deps: Deps) -> StdResult<RestockReportResponse> {
+    let mut beverages = vec![];
+    for item in BEVERAGES.range(deps.storage, None, None, Order::Ascending) {
+        let (name, stat) = item?;
+        if stat.is_low() {
+            beverages.push(RestockItem {
+                name: String::from_utf8(name)?,
+                amount: stat.amount.into(),
+                threshold: stat.low_stock.unwrap_or_default().into(),
+                suggested_refill: BEVERAGE_CAPACITY
+                    .saturating_sub(stat.amount)
+                    .saturating_sub(stat.held)
+                    .into(),
+            });
+        }
+    }
+
+    let mut ingredients = vec![];
+    for item in INGREDIENTS.range(deps.storage, None, None, Order::Ascending) {
+        let (name, stat) = item?;
+        if stat.is_low() {
+            ingredients.push(RestockItem {
+                name: String::from_utf8(name)?,
+                amount: stat.amount,
+                threshold: stat.low_stock.unwrap_or_default(),
+                suggested_refill: stat.capacity.saturating_sub(stat.amount),
+            });
+        }
+    }
+
+    Ok(RestockReportResponse {
+        beverages,
+        ingredients,
+    })
+}
+
+fn query_menu(deps: Deps, env: Env) -> StdResult<MenuResponse> {
+    let mut beverages = vec![];
+    for item in BEVERAGES.keys(deps.storage, None, None, Order::Ascending) {
+        let bev_type = String::from_utf8(item)?;
+        let stat = load_beverage(deps.storage, env.block.time, &bev_type)?;
+        beverages.push(MenuBeverage {
+            available: stat.available(env.block.time),
+            price: stat.price,
+            bev_type,
+        });
+    }
+
+    let mut bundles = vec![];
+    for item in BUNDLES.range(deps.storage, None, None, Order::Ascending) {
+        let (name, bundle) = item?;
+        let mut available = u8::MAX;
+        for item in &bundle.items {
+            let stat = BEVERAGES.load(deps.storage, &item.bev_type)?;
+            available = available.min(stat.available(env.block.time) / item.quantity);
+        }
+        bundles.push(MenuBundle {
+            name: String::from_utf8(name)?,
+            price: bundle.price,
+            items: bundle.items,
+            available,
+        });
+    }
+
+    Ok(MenuResponse { beverages, bundles })
+}
+
+fn query_revenue(deps: Deps) -> StdResult<RevenueResponse> {
+    let mut off_chain = Uint128::zero();
+    let mut off_chain_by_method = vec![];
+    for item in OFF_CHAIN_REVENUE.range(deps.storage, None, None, Order::Ascending) {
+        let (method, amount) = item?;
+        off_chain += amount;
+        off_chain_by_method.push(MethodRevenue {
+            method: String::from_utf8(method)?,
+            amount,
+        });
+    }
+
+    Ok(RevenueResponse {
+        on_chain: ON_CHAIN_REVENUE.load(deps.storage)?,
+        off_chain,
+        off_chain_by_method,
+    })
+}
+
+// settings for pagination
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
+fn query_inventory_ledger(
+    deps: Deps,
+    bev_type: Option<String>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<InventoryLedgerResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(U64Key::from(id)));
+
+    let adjustments = ADJUSTMENTS
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter(|item| match (item, &bev_type) {
+            (Ok((_, adjustment)), Some(bev_type)) => adjustment.bev_type.eq(bev_type),
+            _ => true,
+        })
+        .take(limit)
+        .map(|item| item.map(|(_, adjustment)| adjustment))
+        .collect::<StdResult<_>>()?;
+
+    Ok(InventoryLedgerResponse { adjustments })
+}
+
+fn query_entitlements(
+    deps: Deps,
+    address: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<EntitlementsResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(U64Key::from(id)));
+
+    let entitlements = ENTITLEMENTS
+        .prefix(&address)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, entitlement)| entitlement))
+        .collect::<StdResult<_>>()?;
+
+    Ok(EntitlementsResponse { entitlements })
+}
+
+fn query_reservations(
+    deps: Deps,
+    owner: Option<String>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<ReservationsResponse> {
+    let owner = owner
+        .map(|owner| deps.api.addr_validate(&owner))
+        .transpose()?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(U64Key::from(id)));
+
+    let reservations = RESERVATIONS
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter(|item| match (item, &owner) {
+            (Ok((_, reservation)), Some(owner)) => reservation.owner.eq(owner),
+            _ => true,
+        })
+        .take(limit)
+        .map(|item| item.map(|(_, reservation)| reservation))
+        .collect::<StdResult<_>>()?;
+
+    Ok(ReservationsResponse { reservations })
+}
+
+fn query_tabs(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<TabsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .map(|address| deps.api.addr_validate(&address))
+        .transpose()?
+        .map(|address| Bound::exclusive(address.as_str()));
+
+    let tabs = TABS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (address, debt) = item?;
+            let address = Addr::unchecked(String::from_utf8(address)?);
+            Ok(TabInfo {
+                limit: CREDIT_LIMITS
+                    .may_load(deps.storage, &address)?
+                    .unwrap_or_default(),
+                address: address.into_string(),
+                debt,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+
+    Ok(TabsResponse { tabs })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::msg::ShareRequest;
+    use crate::state::{BundleItem, IngredientStat, ScarcityCurve, ScarcityStep, Tier, TierConfig};
+
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{attr, from_binary};
+
+    fn do_intantiate(deps: DepsMut, info: MessageInfo) -> Response {
+        let initial_balances = vec![Cw20Coin {
+            address: "addr0".to_string(),
+            amount: Uint128::from(10_u16),
+        }];
+
+        let msg = InstantiateMsg { initial_balances };
+
+        // we can just call .unwrap() to assert this was a success
+        let res = instantiate(deps, mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        res
+    }
+
+    fn query_balance(deps: DepsMut, addr: &str) -> cw20::BalanceResponse {
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::Balance {
+                address: addr.to_string(),
+            },
+        )
+        .unwrap();
+        from_binary(&res).unwrap()
+    }
+
+    #[test]
+    fn proper_initialization() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+
+        do_intantiate(deps.as_mut(), info);
+
+        let value: cw20::BalanceResponse = query_balance(deps.as_mut(), "addr0");
+        assert_eq!(Uint128::from(10_u32), value.balance);
+    }
+
+    #[test]
+    fn test_update_beverage() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+
+        do_intantiate(deps.as_mut(), info);
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(2_u16),
+            },
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::BeverageStat {
+                bev_type: "americano".to_string(),
+            },
+        )
+        .unwrap();
+
+        let value: BeverageStat = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(2_u16), value.price)
+    }
+
+    #[test]
+    fn test_refill_beverage() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+
+        do_intantiate(deps.as_mut(), info);
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(2_u16),
+            },
+        )
+        .unwrap();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            ExecuteMsg::RefillBeverage {
+                bev_type: "americano".to_string(),
+                amount: 20,
+                expires_at: None,
+            },
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::BeverageStat {
+                bev_type: "americano".to_string(),
+            },
+        )
+        .unwrap();
+
+        let value: BeverageStat = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(2_u16), value.price);
+        assert_eq!(20, value.amount);
+
+        // trying to exceed 50 items
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            ExecuteMsg::RefillBeverage {
+                bev_type: "americano".to_string(),
+                amount: 31,
+                expires_at: None,
+            },
+        )
+        .unwrap_err();
+
+        assert!(matches!(res, ContractError::BeverageNumberExceed {}))
+    }
+
+    #[test]
+    fn test_purchase() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(2_u16),
+            },
+        )
+        .unwrap();
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::RefillBeverage {
+                bev_type: "americano".to_string(),
+                amount: 1,
+                expires_at: None,
+            },
+        )
+        .unwrap();
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+
+        let value: cw20::BalanceResponse = query_balance(deps.as_mut(), "addr0");
+        assert_eq!(Uint128::from(8_u32), value.balance);
+        let value: cw20::BalanceResponse =
+            query_balance(deps.as_mut(), env.contract.address.as_ref());
+        assert_eq!(Uint128::from(2_u32), value.balance);
+
+        // trying to purchase one more americano but it should be over
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap_err();
+
+        assert!(matches!(res, ContractError::BeverageIsOver {}));
+
+        // increase the price for americano to 9 coins
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(9_u16),
+            },
+        )
+        .unwrap();
+
+        // trying to purchase one more americano with price 9 coins
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap_err();
+
+        assert!(matches!(res, ContractError::NotEnoughCoins {}))
+    }
+
+    #[test]
+    fn test_withraw() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(2_u16),
+            },
+        )
+        .unwrap();
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::RefillBeverage {
+                bev_type: "americano".to_string(),
+                amount: 1,
+                expires_at: None,
+            },
+        )
+        .unwrap();
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::WithdrawIncome {},
+        )
+        .unwrap();
+
+        let value: cw20::BalanceResponse = query_balance(deps.as_mut(), "admin");
+        assert_eq!(Uint128::from(2_u32), value.balance);
+        let value: cw20::BalanceResponse =
+            query_balance(deps.as_mut(), env.contract.address.as_ref());
+        assert_eq!(Uint128::from(0_u32), value.balance);
+
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info("random_user", &[]),
+            ExecuteMsg::WithdrawIncome {},
+        )
+        .unwrap_err();
+
+        assert!(matches!(res, ContractError::Unauthorized {}))
+    }
+
+    #[test]
+    fn test_purchase_consumes_ingredients() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::UpdateIngredient {
+                ingredient: "milk".to_string(),
+                capacity: Uint128::from(500_u16),
+            },
+            ExecuteMsg::RefillIngredient {
+                ingredient: "milk".to_string(),
+                amount: Uint128::from(300_u16),
+            },
+            ExecuteMsg::SetRecipe {
+                bev_type: "latte".to_string(),
+                recipe: vec![RecipeItem {
+                    ingredient: "milk".to_string(),
+                    amount: Uint128::from(200_u16),
+                }],
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        for (ingredient, amount) in [("mlik", 200_u16), ("milk", 0)] {
+            let res = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("admin", &[]),
+                ExecuteMsg::SetRecipe {
+                    bev_type: "latte".to_string(),
+                    recipe: vec![RecipeItem {
+                        ingredient: ingredient.to_string(),
+                        amount: Uint128::from(amount),
+                    }],
+                },
+            )
+            .unwrap_err();
+            if amount == 0 {
+                assert!(matches!(res, ContractError::InvalidRecipe {}));
+            } else {
+                assert!(matches!(res, ContractError::Std(StdError::NotFound { .. })));
+            }
+        }
+
+        // trying to exceed the milk container capacity
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::RefillIngredient {
+                ingredient: "milk".to_string(),
+                amount: Uint128::from(201_u16),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::IngredientCapacityExceed {}));
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "latte".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::IngredientStat {
+                ingredient: "milk".to_string(),
+            },
+        )
+        .unwrap();
+        let value: IngredientStat = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(100_u16), value.amount);
+
+        // cups are still there but the milk is over
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "latte".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap_err();
+        assert!(
+            matches!(res, ContractError::IngredientIsOver { ingredient } if ingredient == "milk")
+        );
+    }
+
+    #[test]
+    fn test_low_stock() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(1_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "americano".to_string(),
+                amount: 3,
+                expires_at: None,
+            },
+            ExecuteMsg::SetLowStockThreshold {
+                bev_type: "americano".to_string(),
+                threshold: Some(1),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+        assert!(res.events.is_empty());
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::RestockReport {}).unwrap();
+        let value: RestockReportResponse = from_binary(&res).unwrap();
+        assert!(value.beverages.is_empty());
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(1, res.events.len());
+        assert_eq!("low_stock", res.events[0].ty);
+
+        let res = query(deps.as_ref(), env, QueryMsg::RestockReport {}).unwrap();
+        let value: RestockReportResponse = from_binary(&res).unwrap();
+        assert_eq!(
+            vec![RestockItem {
+                name: "americano".to_string(),
+                amount: Uint128::from(1_u16),
+                threshold: Uint128::from(1_u16),
+                suggested_refill: Uint128::from(49_u16),
+            }],
+            value.beverages
+        );
+    }
+
+    #[test]
+    fn test_expired_batches() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(1_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 2,
+                expires_at: Some(env.block.time.plus_seconds(60)),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 1,
+                expires_at: None,
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 1,
+                expires_at: Some(env.block.time),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::PastExpiry {}));
+
+        // the oldest batch goes first
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "latte".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::Batches {
+                bev_type: "latte".to_string(),
+            },
+        )
+        .unwrap();
+        let value: BatchesResponse = from_binary(&res).unwrap();
+        assert_eq!(2, value.batches.len());
+        assert_eq!(1, value.batches[0].amount);
+        assert_eq!(0, value.expired);
+
+        // the first batch is expired so the fresh one is sold
+        env.block.time = env.block.time.plus_seconds(60);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "latte".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "latte".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::BeverageExpired {}));
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::WriteOffExpired {
+                bev_type: "latte".to_string(),
+            },
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            env,
+            QueryMsg::BeverageStat {
+                bev_type: "latte".to_string(),
+            },
+        )
+        .unwrap();
+        let value: BeverageStat = from_binary(&res).unwrap();
+        assert_eq!(0, value.amount);
+        assert!(value.batches.is_empty());
+    }
+
+    #[test]
+    fn test_adjust_stock() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(1_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::AdjustStock {
+                bev_type: "latte".to_string(),
+                delta: -2,
+                reason: AdjustmentReason::Breakage,
+                expires_at: None,
+            },
+            ExecuteMsg::AdjustStock {
+                bev_type: "latte".to_string(),
+                delta: 1,
+                reason: AdjustmentReason::AuditCorrection,
+                expires_at: Some(env.block.time.plus_seconds(60)),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::AdjustStock {
+                bev_type: "latte".to_string(),
+                delta: -5,
+                reason: AdjustmentReason::Theft,
+                expires_at: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::BeverageIsOver {}));
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::AdjustStock {
+                bev_type: "latte".to_string(),
+                delta: -1,
+                reason: AdjustmentReason::Theft,
+                expires_at: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::Unauthorized {}));
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::BeverageStat {
+                bev_type: "latte".to_string(),
+            },
+        )
+        .unwrap();
+        let value: BeverageStat = from_binary(&res).unwrap();
+        assert_eq!(4, value.amount);
+        // found items are tracked like a refilled batch
+        assert_eq!(
+            Some(env.block.time.plus_seconds(60)),
+            value.batches.last().unwrap().expires_at
+        );
+
+        // revenue is untouched
+        let value = query_balance(deps.as_mut(), env.contract.address.as_ref());
+        assert_eq!(Uint128::zero(), value.balance);
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::InventoryLedger {
+                bev_type: Some("latte".to_string()),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let value: InventoryLedgerResponse = from_binary(&res).unwrap();
+        assert_eq!(2, value.adjustments.len());
+        assert_eq!(-2, value.adjustments[0].delta);
+        assert_eq!(AdjustmentReason::Breakage, value.adjustments[0].reason);
+        assert_eq!(Addr::unchecked("admin"), value.adjustments[0].by);
+
+        let res = query(
+            deps.as_ref(),
+            env,
+            QueryMsg::InventoryLedger {
+                bev_type: None,
+                start_after: Some(1),
+                limit: None,
+            },
+        )
+        .unwrap();
+        let value: InventoryLedgerResponse = from_binary(&res).unwrap();
+        assert_eq!(1, value.adjustments.len());
+        assert_eq!(2, value.adjustments[0].id);
+    }
+
+    #[test]
+    fn test_record_external_sale() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "americano".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::UpdateOperators {
+                add: vec!["cashier".to_string()],
+                remove: vec![],
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let sale = ExecuteMsg::RecordExternalSale {
+            bev_type: "americano".to_string(),
+            quantity: 2,
+            amount: Uint128::from(5_u16),
+            method: "cash".to_string(),
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            sale.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::Unauthorized {}));
+
+        execute(deps.as_mut(), env.clone(), mock_info("cashier", &[]), sale).unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::BeverageStat {
+                bev_type: "americano".to_string(),
+            },
+        )
+        .unwrap();
+        let value: BeverageStat = from_binary(&res).unwrap();
+        assert_eq!(2, value.amount);
+
+        let res = query(deps.as_ref(), env, QueryMsg::Revenue {}).unwrap();
+        let value: RevenueResponse = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(2_u16), value.on_chain);
+        assert_eq!(Uint128::from(5_u16), value.off_chain);
+        assert_eq!(
+            vec![MethodRevenue {
+                method: "cash".to_string(),
+                amount: Uint128::from(5_u16),
+            }],
+            value.off_chain_by_method
+        );
+    }
+
+    #[test]
+    fn test_price_rules() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        // Wednesday, 02:23 UTC
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(4_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "americano".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::SetUtcOffset { minutes: 60 },
+            ExecuteMsg::SetPriceRules {
+                bev_type: "americano".to_string(),
+                rules: vec![
+                    // weekends all day long
+                    PriceRule {
+                        weekdays: 0b1100000,
+                        start_minute: 0,
+                        end_minute: 24 * 60,
+                        discount: Discount::Fixed {
+                            amount: Uint128::from(1_u16),
+                        },
+                    },
+                    // Wednesdays from 3am to 4am local time
+                    PriceRule {
+                        weekdays: 0b0000100,
+                        start_minute: 3 * 60,
+                        end_minute: 4 * 60,
+                        discount: Discount::Percent { percent: 50 },
+                    },
+                ],
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::SetPriceRules {
+                bev_type: "americano".to_string(),
+                rules: vec![PriceRule {
+                    weekdays: 0b1111111,
+                    start_minute: 0,
+                    end_minute: 60,
+                    discount: Discount::Percent { percent: 101 },
+                }],
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::InvalidDiscount {}));
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                attr("action", "purchase"),
+                attr("beverage_type", "americano"),
+                attr("price", "2"),
+                attr("price_rule", "1"),
+                attr("quantity", "1"),
+                attr("free_items", "0"),
+                attr("total", "2"),
+                attr("paid_with_points", "0"),
+                attr("cashback", "0"),
+                attr("subscription_items", "0"),
+            ],
+            res.attributes
+        );
+
+        env.block.time = env.block.time.plus_seconds(3600);
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(attr("price", "4"), res.attributes[2]);
+        assert_eq!(attr("price_rule", "none"), res.attributes[3]);
+
+        let res = query(
+            deps.as_ref(),
+            env,
+            QueryMsg::BeverageStat {
+                bev_type: "americano".to_string(),
+            },
+        )
+        .unwrap();
+        let value: BeverageStat = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(4_u16), value.price);
+    }
+
+    #[test]
+    fn test_scheduled_price_change() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+        let now = env.block.time;
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "americano".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::SchedulePriceChange {
+                bev_type: "americano".to_string(),
+                new_price: Uint128::from(3_u16),
+                effective_at: now.plus_seconds(100),
+            },
+            ExecuteMsg::SchedulePriceChange {
+                bev_type: "americano".to_string(),
+                new_price: Uint128::from(5_u16),
+                effective_at: now.plus_seconds(200),
+            },
+            ExecuteMsg::CancelScheduledChange {
+                bev_type: "americano".to_string(),
+                effective_at: now.plus_seconds(200),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::SchedulePriceChange {
+                bev_type: "americano".to_string(),
+                new_price: Uint128::from(1_u16),
+                effective_at: now,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::PastPriceChange {}));
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::ScheduledPriceChanges {
+                bev_type: "americano".to_string(),
+            },
+        )
+        .unwrap();
+        let value: ScheduledPriceChangesResponse = from_binary(&res).unwrap();
+        assert_eq!(
+            vec![PriceChange {
+                new_price: Uint128::from(3_u16),
+                effective_at: now.plus_seconds(100),
+            }],
+            value.changes
+        );
+
+        // the query sees the new price as soon as it is effective
+        env.block.time = now.plus_seconds(100);
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::BeverageStat {
+                bev_type: "americano".to_string(),
+            },
+        )
+        .unwrap();
+        let value: BeverageStat = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(3_u16), value.price);
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(attr("price", "3"), res.attributes[2]);
+
+        // the change is persisted and is not pending anymore
+        assert_eq!(
+            Uint128::from(3_u16),
+            BEVERAGES.load(&deps.storage, "americano").unwrap().price
+        );
+        assert!(PRICE_CHANGES
+            .load(&deps.storage, "americano")
+            .unwrap()
+            .is_empty());
+
+        // a manual update wins over the changes which are already due
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::SchedulePriceChange {
+                bev_type: "americano".to_string(),
+                new_price: Uint128::from(5_u16),
+                effective_at: now.plus_seconds(110),
+            },
+        )
+        .unwrap();
+        env.block.time = now.plus_seconds(120);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(1_u16),
+            },
+        )
+        .unwrap();
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::BeverageStat {
+                bev_type: "americano".to_string(),
+            },
+        )
+        .unwrap();
+        let value: BeverageStat = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(1_u16), value.price);
+
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(attr("price", "1"), res.attributes[2]);
+    }
+
+    #[test]
+    fn test_scarcity_pricing() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(100_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "americano".to_string(),
+                amount: 25,
+                expires_at: None,
+            },
+            ExecuteMsg::SetScarcityPricing {
+                bev_type: "americano".to_string(),
+                pricing: Some(ScarcityPricing {
+                    curve: ScarcityCurve::Linear {
+                        max_markup_percent: 100,
+                    },
+                    floor: Uint128::from(50_u16),
+                    ceiling: Uint128::from(180_u16),
+                }),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let quote = |deps: Deps| -> QuoteResponse {
+            let res = query(
+                deps,
+                mock_env(),
+                QueryMsg::Quote {
+                    bev_type: "americano".to_string(),
+                    quantity: 1,
+                    modifiers: vec![],
+                    buyer: None,
+                    promo_code: None,
+                },
+            )
+            .unwrap();
+            from_binary(&res).unwrap()
+        };
+
+        // half of the capacity is missing
+        let value = quote(deps.as_ref());
+        assert_eq!(Uint128::from(100_u16), value.base_price);
+        assert_eq!(Uint128::from(150_u16), value.price);
+
+        // the ceiling holds the price when the stock is almost over
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::AdjustStock {
+                bev_type: "americano".to_string(),
+                delta: -24,
+                reason: AdjustmentReason::AuditCorrection,
+                expires_at: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(Uint128::from(180_u16), quote(deps.as_ref()).price);
+
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info("admin", &[]),
+            ExecuteMsg::SetScarcityPricing {
+                bev_type: "americano".to_string(),
+                pricing: Some(ScarcityPricing {
+                    curve: ScarcityCurve::Stepped {
+                        steps: vec![
+                            ScarcityStep {
+                                amount: 10,
+                                markup_percent: 10,
+                            },
+                            ScarcityStep {
+                                amount: 1,
+                                markup_percent: 20,
+                            },
+                        ],
+                    },
+                    floor: Uint128::zero(),
+                    ceiling: Uint128::from(1000_u16),
+                }),
+            },
+        )
+        .unwrap();
+        assert_eq!(Uint128::from(120_u16), quote(deps.as_ref()).price);
+    }
+
+    #[test]
+    fn test_quote_matches_purchase() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 3,
+                expires_at: None,
+            },
+            ExecuteMsg::UpdateModifier {
+                modifier: "extra_shot".to_string(),
+                price: Some(Uint128::from(1_u16)),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let quote = |deps: Deps, quantity: u8| -> QuoteResponse {
+            let res = query(
+                deps,
+                mock_env(),
+                QueryMsg::Quote {
+                    bev_type: "latte".to_string(),
+                    quantity,
+                    modifiers: vec!["extra_shot".to_string()],
+                    buyer: Some("addr0".to_string()),
+                    promo_code: None,
+                },
+            )
+            .unwrap();
+            from_binary(&res).unwrap()
+        };
+
+        let value = quote(deps.as_ref(), 4);
+        assert_eq!(Uint128::from(12_u16), value.total);
+        assert!(!value.in_stock);
+        assert_eq!(Some(false), value.enough_balance);
+
+        let value = quote(deps.as_ref(), 2);
         assert_eq!(Uint128::from(2_u16), value.price);
-        assert_eq!(20, value.amount);
+        assert_eq!(Uint128::from(1_u16), value.modifiers_price);
+        assert_eq!(Uint128::from(6_u16), value.total);
+        assert!(value.in_stock);
+        assert_eq!(Some(true), value.enough_balance);
+
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "latte".to_string(),
+                quantity: Some(2),
+                modifiers: vec!["extra_shot".to_string()],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+
+        let value = query_balance(deps.as_mut(), "addr0");
+        assert_eq!(Uint128::from(4_u16), value.balance);
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::Quote {
+                bev_type: "latte".to_string(),
+                quantity: 1,
+                modifiers: vec!["oat_milk".to_string()],
+                buyer: None,
+                promo_code: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(res, StdError::NotFound { .. }));
+    }
+
+    #[test]
+    fn test_promo_code() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(4_u16),
+            },
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "americano".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::RegisterPromoCode {
+                code_hash: Binary::from(hash_code("SPRING")),
+                discount: Discount::Percent { percent: 50 },
+                beverages: vec!["latte".to_string()],
+                max_uses: Some(10),
+                max_uses_per_address: Some(1),
+                expires_at: Some(env.block.time.plus_seconds(3600)),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let purchase_msg = |bev_type: &str, code: &str| ExecuteMsg::Purchase {
+            bev_type: bev_type.to_string(),
+            quantity: None,
+            modifiers: vec![],
+            promo_code: Some(code.to_string()),
+            pay_with_points: None,
+            pickup_code_hash: None,
+        };
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg("latte", "AUTUMN"),
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::InvalidPromoCode {}));
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg("americano", "SPRING"),
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::InvalidPromoCode {}));
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg("latte", "SPRING"),
+        )
+        .unwrap();
+        assert_eq!(attr("total", "2"), res.attributes[6]);
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg("latte", "SPRING"),
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::PromoCodeExhausted {}));
+
+        env.block.time = env.block.time.plus_seconds(3600);
+        let res = query(
+            deps.as_ref(),
+            env,
+            QueryMsg::Quote {
+                bev_type: "latte".to_string(),
+                quantity: 1,
+                modifiers: vec![],
+                buyer: None,
+                promo_code: Some("SPRING".to_string()),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(StdError::generic_err("Promo code is expired"), res);
+    }
+
+    #[test]
+    fn test_loyalty_card() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "americano".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "tea".to_string(),
+                price: Uint128::from(1_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "americano".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "tea".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::SetBeverageCategory {
+                bev_type: "americano".to_string(),
+                category: Some("coffee".to_string()),
+            },
+            ExecuteMsg::SetLoyaltyRule {
+                rule: Some(LoyaltyRule {
+                    every: 3,
+                    category: Some("coffee".to_string()),
+                }),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let purchase_msg = |bev_type: &str, quantity: u8| ExecuteMsg::Purchase {
+            bev_type: bev_type.to_string(),
+            quantity: Some(quantity),
+            modifiers: vec![],
+            promo_code: None,
+            pay_with_points: None,
+            pickup_code_hash: None,
+        };
+        let loyalty_status = |deps: Deps| -> LoyaltyStatusResponse {
+            let res = query(
+                deps,
+                mock_env(),
+                QueryMsg::LoyaltyStatus {
+                    address: "addr0".to_string(),
+                },
+            )
+            .unwrap();
+            from_binary(&res).unwrap()
+        };
+
+        for msg in [purchase_msg("americano", 2), purchase_msg("tea", 1)] {
+            execute(deps.as_mut(), env.clone(), mock_info("addr0", &[]), msg).unwrap();
+        }
+        let value = loyalty_status(deps.as_ref());
+        assert_eq!(2, value.stamps);
+        assert_eq!(Some(1), value.remaining);
+
+        // the third coffee is on the house
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info("addr0", &[]),
+            purchase_msg("americano", 1),
+        )
+        .unwrap();
+        assert_eq!(attr("free_items", "1"), res.attributes[5]);
+        assert_eq!(attr("total", "0"), res.attributes[6]);
+
+        let value = loyalty_status(deps.as_ref());
+        assert_eq!(0, value.stamps);
+        assert_eq!(Some(3), value.remaining);
+
+        let value = query_balance(deps.as_mut(), "addr0");
+        assert_eq!(Uint128::from(5_u16), value.balance);
+    }
+
+    #[test]
+    fn test_tiers() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(4_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::SetTiers {
+                tiers: Some(TiersConfig {
+                    bronze: TierConfig {
+                        min_spend: Uint128::from(4_u16),
+                        discount_percent: 50,
+                    },
+                    silver: TierConfig {
+                        min_spend: Uint128::from(6_u16),
+                        discount_percent: 50,
+                    },
+                    gold: TierConfig {
+                        min_spend: Uint128::from(100_u16),
+                        discount_percent: 100,
+                    },
+                }),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let tier = |deps: Deps, env: Env| -> TierResponse {
+            let res = query(
+                deps,
+                env,
+                QueryMsg::Tier {
+                    address: "addr0".to_string(),
+                },
+            )
+            .unwrap();
+            from_binary(&res).unwrap()
+        };
+        let purchase_msg = ExecuteMsg::Purchase {
+            bev_type: "latte".to_string(),
+            quantity: None,
+            modifiers: vec![],
+            promo_code: None,
+            pay_with_points: None,
+            pickup_code_hash: None,
+        };
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(attr("total", "4"), res.attributes[6]);
+        assert_eq!(
+            TierResponse {
+                tier: Some(Tier::Bronze),
+                spent: Uint128::from(4_u16),
+                next_tier: Some(Tier::Silver),
+                amount_to_next_tier: Some(Uint128::from(2_u16)),
+            },
+            tier(deps.as_ref(), env.clone())
+        );
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg,
+        )
+        .unwrap();
+        assert_eq!(attr("total", "2"), res.attributes[6]);
+        assert_eq!(Some(Tier::Silver), tier(deps.as_ref(), env.clone()).tier);
+
+        // the spend leaves the window after 30 days
+        env.block.time = env.block.time.plus_seconds(SPEND_WINDOW_SECONDS);
+        let value = tier(deps.as_ref(), env);
+        assert_eq!(None, value.tier);
+        assert_eq!(Uint128::zero(), value.spent);
+    }
+
+    #[test]
+    fn test_points() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(4_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::SetPointsConfig {
+                config: Some(PointsConfig {
+                    cashback_percent: 50,
+                    expires_after: Some(3600),
+                }),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let points = |deps: Deps, env: Env| -> PointsResponse {
+            let res = query(
+                deps,
+                env,
+                QueryMsg::Points {
+                    address: "addr0".to_string(),
+                },
+            )
+            .unwrap();
+            from_binary(&res).unwrap()
+        };
+        let purchase_msg = |pay_with_points: Option<u16>| ExecuteMsg::Purchase {
+            bev_type: "latte".to_string(),
+            quantity: None,
+            modifiers: vec![],
+            promo_code: None,
+            pay_with_points: pay_with_points.map(Uint128::from),
+            pickup_code_hash: None,
+        };
+
+        // not enough points
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg(Some(1)),
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::NotEnoughPoints {}));
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg(None),
+        )
+        .unwrap();
+        assert_eq!(attr("cashback", "2"), res.attributes[8]);
+        assert_eq!(
+            Uint128::from(2_u16),
+            points(deps.as_ref(), env.clone()).balance
+        );
+
+        // cashback is paid only for the coins part
+        let balance = BALANCES
+            .load(&deps.storage, &Addr::unchecked("addr0"))
+            .unwrap();
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg(Some(2)),
+        )
+        .unwrap();
+        assert_eq!(attr("paid_with_points", "2"), res.attributes[7]);
+        assert_eq!(attr("cashback", "1"), res.attributes[8]);
+        assert_eq!(
+            balance - Uint128::from(2_u16),
+            BALANCES
+                .load(&deps.storage, &Addr::unchecked("addr0"))
+                .unwrap()
+        );
+        assert_eq!(
+            Uint128::from(1_u16),
+            points(deps.as_ref(), env.clone()).balance
+        );
+
+        // the points expire
+        env.block.time = env.block.time.plus_seconds(3600);
+        let value = points(deps.as_ref(), env.clone());
+        assert_eq!(Uint128::zero(), value.balance);
+        assert!(value.grants.is_empty());
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info("addr0", &[]),
+            purchase_msg(Some(1)),
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::NotEnoughPoints {}));
+    }
+
+    #[test]
+    fn test_bundles() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for (bev_type, price, amount) in [("coffee", 3_u16, 5), ("croissant", 2, 1)] {
+            for msg in [
+                ExecuteMsg::UpdateBeverage {
+                    bev_type: bev_type.to_string(),
+                    price: Uint128::from(price),
+                },
+                ExecuteMsg::RefillBeverage {
+                    bev_type: bev_type.to_string(),
+                    amount,
+                    expires_at: None,
+                },
+            ] {
+                execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+            }
+        }
+
+        let bundle_msg = |price: u16| ExecuteMsg::UpdateBundle {
+            name: "breakfast".to_string(),
+            bundle: Some(Bundle {
+                price: Uint128::from(price),
+                items: vec![
+                    BundleItem {
+                        bev_type: "coffee".to_string(),
+                        quantity: 1,
+                    },
+                    BundleItem {
+                        bev_type: "croissant".to_string(),
+                        quantity: 1,
+                    },
+                ],
+            }),
+        };
+
+        // the bundle must be cheaper than its components
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            bundle_msg(5),
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::InvalidBundle {}));
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            bundle_msg(4),
+        )
+        .unwrap();
+
+        let purchase_msg = ExecuteMsg::PurchaseBundle {
+            name: "breakfast".to_string(),
+            quantity: None,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(attr("total", "4"), res.attributes[3]);
+        assert_eq!(
+            Uint128::from(6_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::Menu {}).unwrap();
+        let menu: MenuResponse = from_binary(&res).unwrap();
+        assert_eq!(
+            vec![
+                MenuBeverage {
+                    bev_type: "coffee".to_string(),
+                    price: Uint128::from(3_u16),
+                    available: 4,
+                },
+                MenuBeverage {
+                    bev_type: "croissant".to_string(),
+                    price: Uint128::from(2_u16),
+                    available: 0,
+                },
+            ],
+            menu.beverages
+        );
+        assert_eq!(1, menu.bundles.len());
+        assert_eq!(0, menu.bundles[0].available);
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg,
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::BeverageIsOver {}));
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::UpdateBundle {
+                name: "breakfast".to_string(),
+                bundle: None,
+            },
+        )
+        .unwrap();
+        let res = query(deps.as_ref(), env, QueryMsg::Menu {}).unwrap();
+        let menu: MenuResponse = from_binary(&res).unwrap();
+        assert!(menu.bundles.is_empty());
+    }
+
+    #[test]
+    fn test_price_breaks() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(4_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 10,
+                expires_at: None,
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let price_break = |min_quantity: u8, percent: u8| PriceBreak {
+            min_quantity,
+            discount: Discount::Percent { percent },
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::SetPriceBreaks {
+                bev_type: "latte".to_string(),
+                breaks: vec![price_break(5, 50), price_break(3, 25)],
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::InvalidPriceBreaks {}));
+
+        let breaks = vec![price_break(3, 25), price_break(5, 50)];
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::SetPriceBreaks {
+                bev_type: "latte".to_string(),
+                breaks: breaks.clone(),
+            },
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::BeverageStat {
+                bev_type: "latte".to_string(),
+            },
+        )
+        .unwrap();
+        let value: BeverageStat = from_binary(&res).unwrap();
+        assert_eq!(breaks, value.price_breaks);
+
+        for (quantity, price, price_break) in [(2, 4_u16, None), (4, 3, Some(3)), (5, 2, Some(5))] {
+            let res = query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::Quote {
+                    bev_type: "latte".to_string(),
+                    quantity,
+                    modifiers: vec![],
+                    buyer: None,
+                    promo_code: None,
+                },
+            )
+            .unwrap();
+            let quote: QuoteResponse = from_binary(&res).unwrap();
+            assert_eq!(Uint128::from(price), quote.price);
+            assert_eq!(price_break, quote.price_break);
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info("addr0", &[]),
+            ExecuteMsg::Purchase {
+                bev_type: "latte".to_string(),
+                quantity: Some(3),
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(attr("total", "9"), res.attributes[6]);
+    }
+
+    #[test]
+    fn test_subscription() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for (bev_type, price) in [("latte", 3_u16), ("tea", 1)] {
+            for msg in [
+                ExecuteMsg::UpdateBeverage {
+                    bev_type: bev_type.to_string(),
+                    price: Uint128::from(price),
+                },
+                ExecuteMsg::RefillBeverage {
+                    bev_type: bev_type.to_string(),
+                    amount: 10,
+                    expires_at: None,
+                },
+            ] {
+                execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+            }
+        }
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::SetBeverageCategory {
+                bev_type: "latte".to_string(),
+                category: Some("coffee".to_string()),
+            },
+        )
+        .unwrap();
+
+        let plan_msg = |drinks: u32| ExecuteMsg::UpdateSubscriptionPlan {
+            name: "monthly".to_string(),
+            plan: Some(SubscriptionPlan {
+                price: Uint128::from(4_u16),
+                drinks,
+                categories: vec!["coffee".to_string()],
+            }),
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            plan_msg(0),
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::InvalidSubscriptionPlan {}));
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            plan_msg(2),
+        )
+        .unwrap();
+
+        let subscribe_msg = ExecuteMsg::Subscribe {
+            plan: "monthly".to_string(),
+            months: Some(2),
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            subscribe_msg.clone(),
+        )
+        .unwrap();
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            subscribe_msg,
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::SubscriptionActive {}));
+
+        let subscription = |deps: Deps, env: Env| -> SubscriptionResponse {
+            let res = query(
+                deps,
+                env,
+                QueryMsg::Subscription {
+                    address: "addr0".to_string(),
+                },
+            )
+            .unwrap();
+            from_binary(&res).unwrap()
+        };
+        let value = subscription(deps.as_ref(), env.clone());
+        // mock time is in October 2019, the subscription lasts until December
+        assert_eq!(
+            Timestamp::from_seconds(1575158400),
+            value.subscription.unwrap().expires_at
+        );
+        assert_eq!(2, value.remaining);
+
+        let purchase_msg = |bev_type: &str| ExecuteMsg::Purchase {
+            bev_type: bev_type.to_string(),
+            quantity: Some(2),
+            modifiers: vec![],
+            promo_code: None,
+            pay_with_points: None,
+            pickup_code_hash: None,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg("latte"),
+        )
+        .unwrap();
+        assert_eq!(attr("total", "0"), res.attributes[6]);
+        assert_eq!(attr("subscription_items", "2"), res.attributes[9]);
+        assert_eq!(0, subscription(deps.as_ref(), env.clone()).remaining);
+
+        // tea is not covered by the plan
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg("tea"),
+        )
+        .unwrap();
+        assert_eq!(attr("total", "2"), res.attributes[6]);
+        assert_eq!(
+            Uint128::zero(),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+
+        // the allowance is renewed in November and ends in December
+        env.block.time = Timestamp::from_seconds(1572566400);
+        assert_eq!(2, subscription(deps.as_ref(), env.clone()).remaining);
+        env.block.time = Timestamp::from_seconds(1575158400);
+        assert_eq!(0, subscription(deps.as_ref(), env).remaining);
+    }
+
+    #[test]
+    fn test_vouchers() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(3_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let expires_at = env.block.time.plus_seconds(100);
+        for (code, value) in [
+            (
+                "secret",
+                VoucherValue::Beverage {
+                    bev_type: "latte".to_string(),
+                },
+            ),
+            (
+                "gift",
+                VoucherValue::Credit {
+                    amount: Uint128::from(2_u16),
+                },
+            ),
+            (
+                "forgotten",
+                VoucherValue::Credit {
+                    amount: Uint128::from(1_u16),
+                },
+            ),
+        ] {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("addr0", &[]),
+                ExecuteMsg::BuyVoucher {
+                    code_hash: Binary::from(hash_code(code)),
+                    value,
+                    expires_at,
+                },
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            Uint128::from(4_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+
+        let redeem = |deps: DepsMut, env: Env, code: &str| {
+            execute(
+                deps,
+                env,
+                mock_info("addr1", &[]),
+                ExecuteMsg::RedeemVoucher {
+                    code: code.to_string(),
+                },
+            )
+        };
+        let res = redeem(deps.as_mut(), env.clone(), "wrong").unwrap_err();
+        assert!(matches!(res, ContractError::InvalidVoucher {}));
+
+        redeem(deps.as_mut(), env.clone(), "secret").unwrap();
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!(4, stat.amount);
+        assert_eq!(
+            Uint128::from(3_u16),
+            query_balance(deps.as_mut(), env.contract.address.as_ref()).balance
+        );
+        // a voucher can be redeemed only once
+        let res = redeem(deps.as_mut(), env.clone(), "secret").unwrap_err();
+        assert!(matches!(res, ContractError::InvalidVoucher {}));
+
+        redeem(deps.as_mut(), env.clone(), "gift").unwrap();
+        assert_eq!(
+            Uint128::from(2_u16),
+            query_balance(deps.as_mut(), "addr1").balance
+        );
+
+        let refund_msg = ExecuteMsg::RefundVoucher {
+            code_hash: Binary::from(hash_code("forgotten")),
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            refund_msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::VoucherNotExpired {}));
+
+        env.block.time = expires_at;
+        let res = redeem(deps.as_mut(), env.clone(), "forgotten").unwrap_err();
+        assert!(matches!(res, ContractError::VoucherExpired {}));
+        execute(deps.as_mut(), env, mock_info("anyone", &[]), refund_msg).unwrap();
+        assert_eq!(
+            Uint128::from(5_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+    }
+
+    #[test]
+    fn test_gift_drink() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(3_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::GiftDrink {
+                bev_type: "latte".to_string(),
+                recipient: "addr1".to_string(),
+                quantity: Some(2),
+            },
+        )
+        .unwrap();
+        assert_eq!(attr("total", "6"), res.attributes[4]);
+        assert_eq!(
+            Uint128::from(4_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+        // the gifted items are held until the drink is claimed
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!((3, 2), (stat.amount, stat.held));
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::GiftDrink {
+                bev_type: "latte".to_string(),
+                recipient: "addr1".to_string(),
+                quantity: Some(4),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::BeverageIsOver {}));
+
+        let entitlements = |deps: Deps| -> Vec<Entitlement> {
+            let res = query(
+                deps,
+                mock_env(),
+                QueryMsg::Entitlements {
+                    address: "addr1".to_string(),
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+            from_binary::<EntitlementsResponse>(&res)
+                .unwrap()
+                .entitlements
+        };
+        assert_eq!(
+            vec![Entitlement {
+                id: 1,
+                bev_type: "latte".to_string(),
+                quantity: 2,
+                from: Addr::unchecked("addr0"),
+                created_at: env.block.time,
+                batches: vec![Batch {
+                    amount: 2,
+                    refilled_at: env.block.time,
+                    expires_at: None,
+                }],
+            }],
+            entitlements(deps.as_ref())
+        );
+
+        // only the recipient can claim
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::ClaimDrink { id: 1 },
+        )
+        .unwrap_err();
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info("addr1", &[]),
+            ExecuteMsg::ClaimDrink { id: 1 },
+        )
+        .unwrap();
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!((3, 0), (stat.amount, stat.held));
+        assert!(entitlements(deps.as_ref()).is_empty());
+    }
+
+    #[test]
+    fn test_reservations() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 3,
+                expires_at: None,
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        for quantity in [2, 1] {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("addr0", &[]),
+                ExecuteMsg::Reserve {
+                    bev_type: "latte".to_string(),
+                    quantity,
+                },
+            )
+            .unwrap();
+        }
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!(0, stat.amount);
+        assert!(stat.batches.is_empty());
+        assert_eq!(
+            Uint128::from(4_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr1", &[]),
+            ExecuteMsg::Collect { id: 1 },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::Unauthorized {}));
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Collect { id: 1 },
+        )
+        .unwrap();
+        assert_eq!(
+            Uint128::from(4_u16),
+            query_balance(deps.as_mut(), env.contract.address.as_ref()).balance
+        );
+
+        env.block.height += DEFAULT_RESERVATION_BLOCKS;
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Collect { id: 2 },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::ReservationExpired {}));
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::ExpireReservations { limit: None },
+        )
+        .unwrap();
+        assert_eq!(attr("expired", "1"), res.attributes[1]);
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!(1, stat.amount);
+        assert_eq!(1, stat.batches.len());
+        assert_eq!(
+            Uint128::from(6_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+
+        let res = query(
+            deps.as_ref(),
+            env,
+            QueryMsg::Reservations {
+                owner: Some("addr0".to_string()),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let value: ReservationsResponse = from_binary(&res).unwrap();
+        assert!(value.reservations.is_empty());
+    }
+
+    #[test]
+    fn test_reserved_items_expire() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: BEVERAGE_CAPACITY,
+                expires_at: Some(env.block.time.plus_seconds(60)),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Reserve {
+                bev_type: "latte".to_string(),
+                quantity: 5,
+            },
+        )
+        .unwrap();
+
+        // The reserved items are still in the machine.
+        for msg in [
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 250,
+                expires_at: None,
+            },
+            ExecuteMsg::AdjustStock {
+                bev_type: "latte".to_string(),
+                delta: 250,
+                reason: AdjustmentReason::AuditCorrection,
+                expires_at: None,
+            },
+        ] {
+            let res =
+                execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap_err();
+            assert!(matches!(res, ContractError::BeverageNumberExceed {}));
+        }
+
+        env.block.time = env.block.time.plus_seconds(60);
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::Collect { id: 1 },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::BeverageExpired {}));
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::ExpireReservations { limit: None },
+        )
+        .unwrap();
+        assert_eq!(attr("expired", "1"), res.attributes[1]);
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!(BEVERAGE_CAPACITY, stat.amount);
+        assert_eq!(0, stat.held);
+        assert_eq!(
+            Uint128::from(10_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+    }
+
+    #[test]
+    fn test_barista_orders() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::UpdateOperators {
+                add: vec!["barista".to_string()],
+                remove: vec![],
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        for _ in 0..2 {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("addr0", &[]),
+                ExecuteMsg::PlaceOrder {
+                    bev_type: "latte".to_string(),
+                    quantity: None,
+                    modifiers: vec![],
+                },
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            Uint128::from(6_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+
+        let advance = |deps: DepsMut, sender: &str| {
+            execute(
+                deps,
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::AdvanceOrder { id: 1 },
+            )
+        };
+        let res = advance(deps.as_mut(), "addr0").unwrap_err();
+        assert!(matches!(res, ContractError::Unauthorized {}));
+        let res = advance(deps.as_mut(), "barista").unwrap();
+        assert_eq!(attr("status", "Preparing"), res.attributes[2]);
+        assert_eq!(4, BEVERAGES.load(&deps.storage, "latte").unwrap().amount);
+
+        // the order being prepared can't be cancelled
+        let cancel = |deps: DepsMut, id: u64| {
+            execute(
+                deps,
+                mock_env(),
+                mock_info("addr0", &[]),
+                ExecuteMsg::CancelOrder { id },
+            )
+        };
+        let res = cancel(deps.as_mut(), 1).unwrap_err();
+        assert!(matches!(res, ContractError::InvalidOrderStatus { .. }));
+
+        let queue = |deps: Deps| -> Vec<BaristaOrder> {
+            let res = query(
+                deps,
+                mock_env(),
+                QueryMsg::OrderQueue {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+            from_binary::<OrdersResponse>(&res).unwrap().orders
+        };
+        let orders = queue(deps.as_ref());
+        assert_eq!(
+            vec![(1, OrderStatus::Preparing), (2, OrderStatus::Placed)],
+            orders
+                .iter()
+                .map(|order| (order.id, order.status))
+                .collect::<Vec<_>>()
+        );
+
+        cancel(deps.as_mut(), 2).unwrap();
+        assert_eq!(
+            Uint128::from(8_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+
+        let res = advance(deps.as_mut(), "barista").unwrap();
+        assert_eq!(attr("status", "Ready"), res.attributes[2]);
+        let res = query(
+            deps.as_ref(),
+            env,
+            QueryMsg::CustomerOrders {
+                customer: "addr0".to_string(),
+            },
+        )
+        .unwrap();
+        let value: OrdersResponse = from_binary(&res).unwrap();
+        assert_eq!(OrderStatus::Ready, value.orders[0].status);
+
+        let res = advance(deps.as_mut(), "barista").unwrap();
+        assert_eq!(attr("status", "Collected"), res.attributes[2]);
+        assert!(queue(deps.as_ref()).is_empty());
+    }
+
+    #[test]
+    fn test_pickup_code() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+        let salt = "5b2e9c0f7a1d4e83";
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 2,
+                expires_at: None,
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
 
-        // trying to exceed 50 items
+        let purchase_msg = ExecuteMsg::Purchase {
+            bev_type: "latte".to_string(),
+            quantity: Some(2),
+            modifiers: vec![],
+            promo_code: None,
+            pay_with_points: None,
+            pickup_code_hash: Some(Binary::from(hash_pickup_code("1234", salt))),
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            Uint128::from(6_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!((0, 2), (stat.amount, stat.held));
+
+        for (quantity, code) in [(0, "5678"), (1, "5678")] {
+            let res = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("addr0", &[]),
+                ExecuteMsg::Purchase {
+                    bev_type: "latte".to_string(),
+                    quantity: Some(quantity),
+                    modifiers: vec![],
+                    promo_code: None,
+                    pay_with_points: None,
+                    pickup_code_hash: Some(Binary::from(hash_pickup_code(code, salt))),
+                },
+            )
+            .unwrap_err();
+            if quantity == 0 {
+                assert!(matches!(res, ContractError::ZeroQuantity {}));
+            } else {
+                assert!(matches!(res, ContractError::BeverageIsOver {}));
+            }
+        }
+
+        // the code is taken until the drink is collected
         let res = execute(
             deps.as_mut(),
-            mock_env(),
-            mock_info("admin", &[]),
-            ExecuteMsg::RefillBeverage {
-                bev_type: "americano".to_string(),
-                amount: 31,
-            },
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg,
         )
         .unwrap_err();
+        assert!(matches!(res, ContractError::InvalidPickupCode {}));
 
-        assert!(matches!(res, ContractError::BeverageNumberExceed {}))
+        let collect = |deps: DepsMut, sender: &str, code: &str, salt: &str| {
+            execute(
+                deps,
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::CollectPickup {
+                    code: code.to_string(),
+                    salt: salt.to_string(),
+                },
+            )
+        };
+        let res = collect(deps.as_mut(), "addr0", "1234", salt).unwrap_err();
+        assert!(matches!(res, ContractError::Unauthorized {}));
+        let res = collect(deps.as_mut(), "admin", "4321", salt).unwrap_err();
+        assert!(matches!(res, ContractError::InvalidPickupCode {}));
+        // a short salt doesn't protect the code
+        let res = collect(deps.as_mut(), "admin", "1234", "").unwrap_err();
+        assert!(matches!(res, ContractError::InvalidPickupCode {}));
+        collect(deps.as_mut(), "admin", "1234", salt).unwrap();
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!((0, 0), (stat.amount, stat.held));
+        let res = collect(deps.as_mut(), "admin", "1234", salt).unwrap_err();
+        assert!(matches!(res, ContractError::InvalidPickupCode {}));
     }
 
     #[test]
-    fn test_purchase() {
+    fn test_backorders() {
         let mut deps = mock_dependencies(&[]);
         let info = mock_info("admin", &[]);
         let env = mock_env();
 
         do_intantiate(deps.as_mut(), info);
 
+        let refill = |deps: DepsMut, amount: u8| {
+            execute(
+                deps,
+                mock_env(),
+                mock_info("admin", &[]),
+                ExecuteMsg::RefillBeverage {
+                    bev_type: "latte".to_string(),
+                    amount,
+                    expires_at: None,
+                },
+            )
+            .unwrap()
+        };
         execute(
             deps.as_mut(),
             env.clone(),
             mock_info("admin", &[]),
             ExecuteMsg::UpdateBeverage {
-                bev_type: "americano".to_string(),
+                bev_type: "latte".to_string(),
                 price: Uint128::from(2_u16),
             },
         )
         .unwrap();
 
+        let backorder = |deps: DepsMut, quantity: u8| {
+            execute(
+                deps,
+                mock_env(),
+                mock_info("addr0", &[]),
+                ExecuteMsg::Backorder {
+                    bev_type: "latte".to_string(),
+                    quantity: Some(quantity),
+                },
+            )
+        };
+        for quantity in [2, 1, 1] {
+            backorder(deps.as_mut(), quantity).unwrap();
+        }
+        assert_eq!(
+            Uint128::from(2_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+
         execute(
             deps.as_mut(),
             env.clone(),
-            mock_info("admin", &[]),
-            ExecuteMsg::RefillBeverage {
-                bev_type: "americano".to_string(),
-                amount: 1,
+            mock_info("addr0", &[]),
+            ExecuteMsg::CancelBackorder {
+                bev_type: "latte".to_string(),
+                id: 3,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            Uint128::from(4_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+
+        // the first backorder doesn't fit, so nobody is served
+        let res = refill(deps.as_mut(), 1);
+        assert_eq!(attr("backorders_served", "0"), res.attributes[3]);
+        let res = backorder(deps.as_mut(), 1).unwrap_err();
+        assert!(matches!(res, ContractError::BeverageInStock {}));
+
+        // only the refilled items are used to serve the backorders
+        let res = refill(deps.as_mut(), 2);
+        assert_eq!(attr("backorders_served", "1"), res.attributes[3]);
+        assert_eq!(1, BEVERAGES.load(&deps.storage, "latte").unwrap().amount);
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::Backorders {
+                bev_type: "latte".to_string(),
             },
         )
         .unwrap();
+        let value: BackordersResponse = from_binary(&res).unwrap();
+        assert_eq!(
+            vec![2],
+            value.backorders.iter().map(|b| b.id).collect::<Vec<_>>()
+        );
+
+        let res = refill(deps.as_mut(), 1);
+        assert_eq!(attr("backorders_served", "1"), res.attributes[3]);
+        assert_eq!(1, BEVERAGES.load(&deps.storage, "latte").unwrap().amount);
+
+        // the held items are not taken again on claim
+        for id in [1, 2] {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("addr0", &[]),
+                ExecuteMsg::ClaimDrink { id },
+            )
+            .unwrap();
+        }
+        assert_eq!(1, BEVERAGES.load(&deps.storage, "latte").unwrap().amount);
+        assert_eq!(
+            Uint128::from(6_u16),
+            query_balance(deps.as_mut(), env.contract.address.as_ref()).balance
+        );
+    }
+
+    #[test]
+    fn test_backordered_items_expire() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
 
         execute(
             deps.as_mut(),
             env.clone(),
-            mock_info("addr0", &[]),
-            ExecuteMsg::Purchase {
-                bev_type: "americano".to_string(),
+            mock_info("admin", &[]),
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(2_u16),
             },
         )
         .unwrap();
+        for _ in 0..2 {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("addr0", &[]),
+                ExecuteMsg::Backorder {
+                    bev_type: "latte".to_string(),
+                    quantity: Some(1),
+                },
+            )
+            .unwrap();
+        }
 
-        let value: cw20::BalanceResponse = query_balance(deps.as_mut(), "addr0");
-        assert_eq!(Uint128::from(8_u32), value.balance);
-        let value: cw20::BalanceResponse =
-            query_balance(deps.as_mut(), &env.contract.address.to_string());
-        assert_eq!(Uint128::from(2_u32), value.balance);
-
-        // trying to purchase one more americano but it should be over
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("admin", &[]),
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 1,
+                expires_at: Some(env.block.time.plus_seconds(60)),
+            },
+        )
+        .unwrap();
+        // items found on a recount serve the queue as well
         let res = execute(
             deps.as_mut(),
             env.clone(),
-            mock_info("addr0", &[]),
-            ExecuteMsg::Purchase {
-                bev_type: "americano".to_string(),
+            mock_info("admin", &[]),
+            ExecuteMsg::AdjustStock {
+                bev_type: "latte".to_string(),
+                delta: 2,
+                reason: AdjustmentReason::AuditCorrection,
+                expires_at: None,
             },
         )
-        .unwrap_err();
-
-        assert!(matches!(res, ContractError::BeverageIsOver {}));
+        .unwrap();
+        assert_eq!(attr("backorders_served", "1"), res.attributes[4]);
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!((1, 2), (stat.amount, stat.held));
 
-        // increase the price for americano to 9 coins
+        // the expired held item is written off and replaced from the stock
+        env.block.time = env.block.time.plus_seconds(60);
         execute(
             deps.as_mut(),
             env.clone(),
-            mock_info("admin", &[]),
+            mock_info("addr0", &[]),
+            ExecuteMsg::ClaimDrink { id: 1 },
+        )
+        .unwrap();
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!((0, 1), (stat.amount, stat.held));
+        let adjustment = ADJUSTMENTS.load(&deps.storage, 2.into()).unwrap();
+        assert_eq!(
+            (-1, AdjustmentReason::Spoilage),
+            (adjustment.delta, adjustment.reason)
+        );
+
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info("addr0", &[]),
+            ExecuteMsg::ClaimDrink { id: 2 },
+        )
+        .unwrap();
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!((0, 0), (stat.amount, stat.held));
+    }
+
+    #[test]
+    fn test_refunds() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
             ExecuteMsg::UpdateBeverage {
-                bev_type: "americano".to_string(),
-                price: Uint128::from(9_u16),
+                bev_type: "latte".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::SetArbiter {
+                arbiter: Some("arbiter".to_string()),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let purchased_at = env.block.time;
+        for _ in 0..2 {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("addr0", &[]),
+                ExecuteMsg::Purchase {
+                    bev_type: "latte".to_string(),
+                    quantity: None,
+                    modifiers: vec![],
+                    promo_code: None,
+                    pay_with_points: None,
+                    pickup_code_hash: None,
+                },
+            )
+            .unwrap();
+        }
+
+        let request_refund = |deps: DepsMut, env: Env, bev_type: &str| {
+            execute(
+                deps,
+                env,
+                mock_info("addr0", &[]),
+                ExecuteMsg::RequestRefund {
+                    bev_type: bev_type.to_string(),
+                    purchased_at,
+                    reason: "nothing came out".to_string(),
+                },
+            )
+        };
+        let res = request_refund(deps.as_mut(), env.clone(), "tea").unwrap_err();
+        assert!(matches!(res, ContractError::PurchaseNotFound {}));
+        for _ in 0..2 {
+            request_refund(deps.as_mut(), env.clone(), "latte").unwrap();
+        }
+        // both purchases are disputed already
+        let res = request_refund(deps.as_mut(), env.clone(), "latte").unwrap_err();
+        assert!(matches!(res, ContractError::PurchaseNotFound {}));
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::Disputes {
+                start_after: None,
+                limit: None,
             },
         )
         .unwrap();
+        let value: DisputesResponse = from_binary(&res).unwrap();
+        assert_eq!(2, value.disputes.len());
+        assert_eq!("nothing came out", value.disputes[0].reason);
 
-        // trying to purchase one more americano with price 9 coins
-        let res = execute(
+        let resolve = |deps: DepsMut, sender: &str, id: u64, approve: bool| {
+            execute(
+                deps,
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::ResolveRefund {
+                    id,
+                    approve,
+                    restock: true,
+                },
+            )
+        };
+        let res = resolve(deps.as_mut(), "addr0", 1, true).unwrap_err();
+        assert!(matches!(res, ContractError::Unauthorized {}));
+        resolve(deps.as_mut(), "arbiter", 1, true).unwrap();
+        resolve(deps.as_mut(), "admin", 2, false).unwrap();
+
+        assert_eq!(
+            Uint128::from(8_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+        assert_eq!(
+            Uint128::from(2_u16),
+            query_balance(deps.as_mut(), env.contract.address.as_ref()).balance
+        );
+        assert_eq!(4, BEVERAGES.load(&deps.storage, "latte").unwrap().amount);
+
+        execute(
             deps.as_mut(),
-            env,
+            env.clone(),
             mock_info("addr0", &[]),
             ExecuteMsg::Purchase {
-                bev_type: "americano".to_string(),
+                bev_type: "latte".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
             },
         )
-        .unwrap_err();
-
-        assert!(matches!(res, ContractError::NotEnoughCoins {}))
+        .unwrap();
+        env.block.time = env.block.time.plus_seconds(DEFAULT_REFUND_WINDOW_SECONDS);
+        let res = request_refund(deps.as_mut(), env, "latte").unwrap_err();
+        assert!(matches!(res, ContractError::RefundWindowClosed {}));
     }
 
     #[test]
-    fn test_withraw() {
+    fn test_refund_reversal() {
         let mut deps = mock_dependencies(&[]);
         let info = mock_info("admin", &[]);
-        let env = mock_env();
+        let mut env = mock_env();
 
         do_intantiate(deps.as_mut(), info);
 
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(2_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 10,
+                expires_at: None,
+            },
+            ExecuteMsg::SetPointsConfig {
+                config: Some(PointsConfig {
+                    cashback_percent: 50,
+                    expires_after: None,
+                }),
+            },
+            ExecuteMsg::SetLoyaltyRule {
+                rule: Some(LoyaltyRule {
+                    every: 10,
+                    category: None,
+                }),
+            },
+            ExecuteMsg::SetCreditLimit {
+                address: "addr0".to_string(),
+                limit: Some(Uint128::from(4_u16)),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        for (quantity, pickup_code_hash) in [
+            (2, None),
+            (4, Some(hash_pickup_code("1234", "5b2e9c0f7a1d4e83"))),
+        ] {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("addr0", &[]),
+                ExecuteMsg::Purchase {
+                    bev_type: "latte".to_string(),
+                    quantity: Some(quantity),
+                    modifiers: vec![],
+                    promo_code: None,
+                    pay_with_points: None,
+                    pickup_code_hash: pickup_code_hash.map(Binary::from),
+                },
+            )
+            .unwrap();
+            env.block.time = env.block.time.plus_seconds(10);
+        }
+        let addr0 = Addr::unchecked("addr0");
+        assert_eq!(
+            Uint128::from(2_u16),
+            TABS.load(&deps.storage, &addr0).unwrap()
+        );
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!((4, 4), (stat.amount, stat.held));
+
         execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::RequestRefund {
+                bev_type: "latte".to_string(),
+                purchased_at: env.block.time.minus_seconds(10),
+                reason: "never collected".to_string(),
+            },
+        )
+        .unwrap();
+        let res = execute(
             deps.as_mut(),
             env.clone(),
             mock_info("admin", &[]),
-            ExecuteMsg::UpdateBeverage {
-                bev_type: "americano".to_string(),
-                price: Uint128::from(2_u16),
+            ExecuteMsg::ResolveRefund {
+                id: 1,
+                approve: true,
+                restock: true,
             },
         )
         .unwrap();
+        assert_eq!(attr("refund", "6"), res.attributes[3]);
+
+        // only the first purchase is left
+        assert_eq!(
+            Uint128::from(6_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+        assert!(!TABS.has(&deps.storage, &addr0));
+        let points: Uint128 = POINTS
+            .load(&deps.storage, &addr0)
+            .unwrap()
+            .iter()
+            .map(|grant| grant.amount)
+            .sum();
+        assert_eq!(Uint128::from(2_u16), points);
+        assert_eq!(
+            vec![Uint128::from(4_u16)],
+            SPENDINGS
+                .load(&deps.storage, &addr0)
+                .unwrap()
+                .iter()
+                .map(|spending| spending.amount)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(2, LOYALTY_CARDS.load(&deps.storage, &addr0).unwrap());
+        assert_eq!(
+            Uint128::from(4_u16),
+            ON_CHAIN_REVENUE.load(&deps.storage).unwrap()
+        );
+
+        // the pickup is cancelled instead of restocking the items
+        assert!(!PICKUPS.has(&deps.storage, &hash_pickup_code("1234", "5b2e9c0f7a1d4e83")));
+        let stat = BEVERAGES.load(&deps.storage, "latte").unwrap();
+        assert_eq!((8, 0), (stat.amount, stat.held));
 
+        for msg in [
+            ExecuteMsg::UpdateSubscriptionPlan {
+                name: "monthly".to_string(),
+                plan: Some(SubscriptionPlan {
+                    price: Uint128::from(2_u16),
+                    drinks: 1,
+                    categories: vec![],
+                }),
+            },
+            ExecuteMsg::RegisterPromoCode {
+                code_hash: Binary::from(hash_code("SPRING")),
+                discount: Discount::Percent { percent: 50 },
+                beverages: vec![],
+                max_uses: Some(10),
+                max_uses_per_address: Some(1),
+                expires_at: None,
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
         execute(
             deps.as_mut(),
             env.clone(),
-            mock_info("admin", &[]),
-            ExecuteMsg::RefillBeverage {
-                bev_type: "americano".to_string(),
-                amount: 1,
+            mock_info("addr0", &[]),
+            ExecuteMsg::Subscribe {
+                plan: "monthly".to_string(),
+                months: Some(1),
             },
         )
         .unwrap();
+        let points = |deps: Deps| -> Uint128 {
+            POINTS
+                .load(deps.storage, &addr0)
+                .unwrap()
+                .iter()
+                .map(|grant| grant.amount)
+                .sum()
+        };
+        let points_before = points(deps.as_ref());
+
+        // a drink from the subscription and the rest paid with points
+        let purchase_msg = ExecuteMsg::Purchase {
+            bev_type: "latte".to_string(),
+            quantity: Some(2),
+            modifiers: vec![],
+            promo_code: Some("SPRING".to_string()),
+            pay_with_points: Some(points_before),
+            pickup_code_hash: None,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            purchase_msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(attr("paid_with_points", "1"), res.attributes[7]);
+        assert_eq!(1, SUBSCRIPTIONS.load(&deps.storage, &addr0).unwrap().used);
 
         execute(
             deps.as_mut(),
             env.clone(),
             mock_info("addr0", &[]),
-            ExecuteMsg::Purchase {
-                bev_type: "americano".to_string(),
+            ExecuteMsg::RequestRefund {
+                bev_type: "latte".to_string(),
+                purchased_at: env.block.time,
+                reason: "wrong order".to_string(),
             },
         )
         .unwrap();
-
         execute(
             deps.as_mut(),
             env.clone(),
             mock_info("admin", &[]),
-            ExecuteMsg::WithdrawIncome {},
+            ExecuteMsg::ResolveRefund {
+                id: 2,
+                approve: true,
+                restock: true,
+            },
         )
         .unwrap();
+        assert_eq!(points_before, points(deps.as_ref()));
+        assert_eq!(0, SUBSCRIPTIONS.load(&deps.storage, &addr0).unwrap().used);
+        assert_eq!(
+            0,
+            PROMO_CODES
+                .load(&deps.storage, &hash_code("SPRING"))
+                .unwrap()
+                .uses
+        );
+        // the promo code can be used once more
+        execute(deps.as_mut(), env, mock_info("addr0", &[]), purchase_msg).unwrap();
+    }
 
-        let value: cw20::BalanceResponse = query_balance(deps.as_mut(), "admin");
-        assert_eq!(Uint128::from(2_u32), value.balance);
-        let value: cw20::BalanceResponse =
-            query_balance(deps.as_mut(), &env.contract.address.to_string());
-        assert_eq!(Uint128::from(0_u32), value.balance);
+    #[test]
+    fn test_tabs() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(4_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+            ExecuteMsg::SetCreditLimit {
+                address: "addr0".to_string(),
+                limit: Some(Uint128::from(5_u16)),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+
+        let purchase = |deps: DepsMut, quantity: u8| {
+            execute(
+                deps,
+                mock_env(),
+                mock_info("addr0", &[]),
+                ExecuteMsg::Purchase {
+                    bev_type: "latte".to_string(),
+                    quantity: Some(quantity),
+                    modifiers: vec![],
+                    promo_code: None,
+                    pay_with_points: None,
+                    pickup_code_hash: None,
+                },
+            )
+        };
+        let enough_balance = |deps: Deps| -> Option<bool> {
+            let res = query(
+                deps,
+                mock_env(),
+                QueryMsg::Quote {
+                    bev_type: "latte".to_string(),
+                    quantity: 1,
+                    modifiers: vec![],
+                    buyer: Some("addr0".to_string()),
+                    promo_code: None,
+                },
+            )
+            .unwrap();
+            from_binary::<QuoteResponse>(&res).unwrap().enough_balance
+        };
+        purchase(deps.as_mut(), 2).unwrap();
+        // the credit line covers what the balance can't
+        assert_eq!(Some(true), enough_balance(deps.as_ref()));
+        let res = purchase(deps.as_mut(), 1).unwrap();
+        assert_eq!(Some(&attr("on_tab", "2")), res.attributes.last());
+        assert_eq!(Some(false), enough_balance(deps.as_ref()));
+        assert_eq!(
+            Uint128::zero(),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+        // the debt would exceed the limit
+        let res = purchase(deps.as_mut(), 1).unwrap_err();
+        assert!(matches!(res, ContractError::NotEnoughCoins {}));
+
+        let tabs = |deps: Deps| -> Vec<TabInfo> {
+            let res = query(
+                deps,
+                mock_env(),
+                QueryMsg::Tabs {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap();
+            from_binary::<TabsResponse>(&res).unwrap().tabs
+        };
+        assert_eq!(
+            vec![TabInfo {
+                address: "addr0".to_string(),
+                debt: Uint128::from(2_u16),
+                limit: Uint128::from(5_u16),
+            }],
+            tabs(deps.as_ref())
+        );
+        assert_eq!(
+            Uint128::from(10_u16),
+            query_balance(deps.as_mut(), env.contract.address.as_ref()).balance
+        );
+
+        BALANCES
+            .save(
+                deps.as_mut().storage,
+                &Addr::unchecked("addr0"),
+                &Uint128::from(3_u16),
+            )
+            .unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr0", &[]),
+            ExecuteMsg::SettleTab { amount: None },
+        )
+        .unwrap();
+        assert!(tabs(deps.as_ref()).is_empty());
+        assert_eq!(
+            Uint128::from(1_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
+        assert_eq!(
+            Uint128::from(12_u16),
+            query_balance(deps.as_mut(), env.contract.address.as_ref()).balance
+        );
+    }
+
+    #[test]
+    fn test_group_order() {
+        let mut deps = mock_dependencies(&[]);
+        let info = mock_info("admin", &[]);
+        let mut env = mock_env();
+
+        do_intantiate(deps.as_mut(), info);
+
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(3_u16),
+            },
+            ExecuteMsg::RefillBeverage {
+                bev_type: "latte".to_string(),
+                amount: 5,
+                expires_at: None,
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
+        BALANCES
+            .save(
+                deps.as_mut().storage,
+                &Addr::unchecked("addr1"),
+                &Uint128::from(5_u16),
+            )
+            .unwrap();
 
+        let expires_at = env.block.time.plus_seconds(600);
+        let create_msg = |first_share: u16| ExecuteMsg::CreateGroupOrder {
+            bev_type: "latte".to_string(),
+            quantity: Some(2),
+            modifiers: vec![],
+            shares: vec![
+                ShareRequest {
+                    address: "addr0".to_string(),
+                    amount: Uint128::from(first_share),
+                },
+                ShareRequest {
+                    address: "addr1".to_string(),
+                    amount: Uint128::from(2_u16),
+                },
+            ],
+            expires_at,
+        };
         let res = execute(
             deps.as_mut(),
-            env,
-            mock_info("random_user", &[]),
-            ExecuteMsg::WithdrawIncome {},
+            env.clone(),
+            mock_info("addr2", &[]),
+            create_msg(3),
         )
         .unwrap_err();
+        assert!(matches!(res, ContractError::InvalidShares {}));
+        for _ in 0..2 {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("addr2", &[]),
+                create_msg(4),
+            )
+            .unwrap();
+        }
+        for msg in [
+            ExecuteMsg::UpdateBeverage {
+                bev_type: "latte".to_string(),
+                price: Uint128::from(5_u16),
+            },
+            ExecuteMsg::SetPointsConfig {
+                config: Some(PointsConfig {
+                    cashback_percent: 50,
+                    expires_after: None,
+                }),
+            },
+        ] {
+            execute(deps.as_mut(), env.clone(), mock_info("admin", &[]), msg).unwrap();
+        }
 
-        assert!(matches!(res, ContractError::Unauthorized {}))
+        let fund = |deps: DepsMut, sender: &str, id: u64| {
+            execute(
+                deps,
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::FundGroupOrder { id },
+            )
+        };
+        fund(deps.as_mut(), "addr0", 1).unwrap();
+        let res = fund(deps.as_mut(), "addr0", 1).unwrap_err();
+        assert!(matches!(res, ContractError::ShareAlreadyFunded {}));
+        let res = fund(deps.as_mut(), "addr3", 1).unwrap_err();
+        assert!(matches!(res, ContractError::Unauthorized {}));
+
+        // the last share sells the items at the quoted total
+        let res = fund(deps.as_mut(), "addr1", 1).unwrap();
+        assert_eq!(attr("action", "fund_group_order"), res.attributes[0]);
+        assert_eq!(attr("price", "3"), res.attributes[4]);
+        assert_eq!(attr("total", "6"), res.attributes[6]);
+        assert_eq!(3, BEVERAGES.load(&deps.storage, "latte").unwrap().amount);
+        for (address, balance, points) in
+            [("addr0", 6_u16, 2_u16), ("addr1", 3, 1), ("addr2", 0, 0)]
+        {
+            assert_eq!(
+                Uint128::from(balance),
+                query_balance(deps.as_mut(), address).balance
+            );
+            let points_granted: Uint128 = POINTS
+                .may_load(&deps.storage, &Addr::unchecked(address))
+                .unwrap()
+                .unwrap_or_default()
+                .iter()
+                .map(|grant| grant.amount)
+                .sum();
+            assert_eq!(Uint128::from(points), points_granted);
+        }
+        assert_eq!(
+            Uint128::from(6_u16),
+            query_balance(deps.as_mut(), env.contract.address.as_ref()).balance
+        );
+        assert!(!PURCHASES.has(&deps.storage, &Addr::unchecked("addr2")));
+
+        fund(deps.as_mut(), "addr0", 2).unwrap();
+        let expire_msg = ExecuteMsg::ExpireGroupOrder { id: 2 };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            expire_msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::GroupOrderNotExpired {}));
+
+        env.block.time = expires_at;
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("addr1", &[]),
+            ExecuteMsg::FundGroupOrder { id: 2 },
+        )
+        .unwrap_err();
+        assert!(matches!(res, ContractError::GroupOrderExpired {}));
+        execute(deps.as_mut(), env, mock_info("anyone", &[]), expire_msg).unwrap();
+        assert_eq!(
+            Uint128::from(6_u16),
+            query_balance(deps.as_mut(), "addr0").balance
+        );
     }
 
     #[test]
@@ -488,6 +6597,11 @@ mod tests {
             mock_info("addr0", &[]),
             ExecuteMsg::Purchase {
                 bev_type: "americano".to_string(),
+                quantity: None,
+                modifiers: vec![],
+                promo_code: None,
+                pay_with_points: None,
+                pickup_code_hash: None,
             },
         )
         .unwrap_err();
@@ -15,6 +15,117 @@ pub enum ContractError {
     #[error("The beverage is over")]
     BeverageIsOver {},
 
+    #[error("Only expired beverages are left")]
+    BeverageExpired {},
+
+    #[error("Batch must expire in the future")]
+    PastExpiry {},
+
     #[error("Not enough coins")]
     NotEnoughCoins {},
+
+    #[error("Discount percent must not exceed 100")]
+    InvalidDiscount {},
+
+    #[error("Invalid price rule")]
+    InvalidPriceRule {},
+
+    #[error("Invalid price breaks")]
+    InvalidPriceBreaks {},
+
+    #[error("UTC offset must be within 14 hours")]
+    InvalidUtcOffset {},
+
+    #[error("Price floor must not exceed the ceiling")]
+    InvalidScarcityPricing {},
+
+    #[error("Loyalty rule must stamp at least one beverage")]
+    InvalidLoyaltyRule {},
+
+    #[error("Tier thresholds must grow and discounts must not exceed 100 percent")]
+    InvalidTiers {},
+
+    #[error("Invalid bundle")]
+    InvalidBundle {},
+
+    #[error("Invalid subscription plan")]
+    InvalidSubscriptionPlan {},
+
+    #[error("Subscription is still active")]
+    SubscriptionActive {},
+
+    #[error("Invalid voucher")]
+    InvalidVoucher {},
+
+    #[error("Voucher expired")]
+    VoucherExpired {},
+
+    #[error("Voucher is not expired yet")]
+    VoucherNotExpired {},
+
+    #[error("Reservation expired")]
+    ReservationExpired {},
+
+    #[error("Order is {status}")]
+    InvalidOrderStatus { status: String },
+
+    #[error("Invalid pickup code")]
+    InvalidPickupCode {},
+
+    #[error("Beverage is in stock")]
+    BeverageInStock {},
+
+    #[error("Purchase not found")]
+    PurchaseNotFound {},
+
+    #[error("Refund window is closed")]
+    RefundWindowClosed {},
+
+    #[error("Shares must cover the total")]
+    InvalidShares {},
+
+    #[error("Share is already funded")]
+    ShareAlreadyFunded {},
+
+    #[error("Group order expired")]
+    GroupOrderExpired {},
+
+    #[error("Group order is not expired yet")]
+    GroupOrderNotExpired {},
+
+    #[error("Invalid points config")]
+    InvalidPointsConfig {},
+
+    #[error("Promo code is invalid")]
+    InvalidPromoCode {},
+
+    #[error("Promo code is expired")]
+    PromoCodeExpired {},
+
+    #[error("Promo code usage limit reached")]
+    PromoCodeExhausted {},
+
+    #[error("Price change must be scheduled in the future")]
+    PastPriceChange {},
+
+    #[error("Scheduled price change not found")]
+    PriceChangeNotFound {},
+
+    #[error("Quantity must not be zero")]
+    ZeroQuantity {},
+
+    #[error("Stock adjustment must not be zero")]
+    ZeroAdjustment {},
+
+    #[error("Not enough points")]
+    NotEnoughPoints {},
+
+    #[error("Recipe amounts must not be zero")]
+    InvalidRecipe {},
+
+    #[error("Ingredient capacity exceed")]
+    IngredientCapacityExceed {},
+
+    #[error("Not enough {ingredient}")]
+    IngredientIsOver { ingredient: String },
 }
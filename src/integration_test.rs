@@ -34,12 +34,18 @@ pub fn refill_beverage_msg(name: &str, amount: u8) -> ExecuteMsg {
     ExecuteMsg::RefillBeverage {
         bev_type: String::from(name),
         amount,
+        expires_at: None,
     }
 }
 
 pub fn purchase_msg(name: &str) -> ExecuteMsg {
     ExecuteMsg::Purchase {
         bev_type: String::from(name),
+        quantity: None,
+        modifiers: vec![],
+        promo_code: None,
+        pay_with_points: None,
+        pickup_code_hash: None,
     }
 }
 
@@ -1,8 +1,15 @@
-use cosmwasm_std::Uint128;
+use cosmwasm_std::{Binary, Timestamp, Uint128};
 use cw20::Cw20Coin;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::state::{
+    Adjustment, AdjustmentReason, Backorder, BaristaOrder, Batch, Bundle, BundleItem, Discount,
+    Dispute, Entitlement, LoyaltyRule, PointsConfig, PointsGrant, PriceBreak, PriceChange,
+    PriceRule, RecipeItem, Reservation, ScarcityPricing, Subscription, SubscriptionPlan, Tier,
+    TiersConfig, VoucherValue,
+};
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
     pub initial_balances: Vec<Cw20Coin>,
@@ -11,15 +18,551 @@ pub struct InstantiateMsg {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
-    UpdateBeverage { bev_type: String, price: Uint128 },
-    RefillBeverage { bev_type: String, amount: u8 },
-    Purchase { bev_type: String },
+    UpdateBeverage {
+        bev_type: String,
+        price: Uint128,
+    },
+    RefillBeverage {
+        bev_type: String,
+        amount: u8,
+        expires_at: Option<Timestamp>,
+    },
+    WriteOffExpired {
+        bev_type: String,
+    },
+    AdjustStock {
+        bev_type: String,
+        delta: i16,
+        reason: AdjustmentReason,
+        /// Expiry of the items added by a positive delta.
+        #[serde(default)]
+        expires_at: Option<Timestamp>,
+    },
+    UpdateIngredient {
+        ingredient: String,
+        capacity: Uint128,
+    },
+    RefillIngredient {
+        ingredient: String,
+        amount: Uint128,
+    },
+    SetRecipe {
+        bev_type: String,
+        recipe: Vec<RecipeItem>,
+    },
+    SetLowStockThreshold {
+        bev_type: String,
+        threshold: Option<u8>,
+    },
+    SetIngredientLowStockThreshold {
+        ingredient: String,
+        threshold: Option<Uint128>,
+    },
+    SetPriceRules {
+        bev_type: String,
+        rules: Vec<PriceRule>,
+    },
+    SetUtcOffset {
+        minutes: i16,
+    },
+    SetScarcityPricing {
+        bev_type: String,
+        pricing: Option<ScarcityPricing>,
+    },
+    SchedulePriceChange {
+        bev_type: String,
+        new_price: Uint128,
+        effective_at: Timestamp,
+    },
+    CancelScheduledChange {
+        bev_type: String,
+        effective_at: Timestamp,
+    },
+    UpdateModifier {
+        modifier: String,
+        price: Option<Uint128>,
+    },
+    /// Breaks must be sorted by `min_quantity`, starting from 2 items.
+    SetPriceBreaks {
+        bev_type: String,
+        breaks: Vec<PriceBreak>,
+    },
+    SetBeverageCategory {
+        bev_type: String,
+        category: Option<String>,
+    },
+    SetLoyaltyRule {
+        rule: Option<LoyaltyRule>,
+    },
+    SetTiers {
+        tiers: Option<TiersConfig>,
+    },
+    RegisterPromoCode {
+        /// SHA-256 hash of the code.
+        code_hash: Binary,
+        discount: Discount,
+        beverages: Vec<String>,
+        max_uses: Option<u32>,
+        max_uses_per_address: Option<u32>,
+        expires_at: Option<Timestamp>,
+    },
+    RemovePromoCode {
+        code_hash: Binary,
+    },
+    Purchase {
+        bev_type: String,
+        /// One item if not set.
+        quantity: Option<u8>,
+        #[serde(default)]
+        modifiers: Vec<String>,
+        promo_code: Option<String>,
+        /// Points to spend instead of coins, capped by the total.
+        pay_with_points: Option<Uint128>,
+        /// SHA-256 hash of a pickup code followed by a secret salt of at
+        /// least 16 bytes. If set, the items are held in the machine and
+        /// released by `CollectPickup` instead of right away.
+        pickup_code_hash: Option<Binary>,
+    },
+    /// Releases a purchase made with a pickup code, operators only.
+    CollectPickup {
+        code: String,
+        salt: String,
+    },
+    /// Removes the bundle if `bundle` is not set.
+    UpdateBundle {
+        name: String,
+        bundle: Option<Bundle>,
+    },
+    PurchaseBundle {
+        name: String,
+        quantity: Option<u8>,
+    },
+    /// Removes the plan if `plan` is not set.
+    UpdateSubscriptionPlan {
+        name: String,
+        plan: Option<SubscriptionPlan>,
+    },
+    /// Pays for the plan until the end of the `months`-th calendar month, one by default.
+    Subscribe {
+        plan: String,
+        months: Option<u8>,
+    },
+    /// Prepays a beverage at its current price or an amount of credits.
+    BuyVoucher {
+        /// SHA-256 hash of the code.
+        code_hash: Binary,
+        value: VoucherValue,
+        expires_at: Timestamp,
+    },
+    RedeemVoucher {
+        code: String,
+    },
+    /// Refunds an expired voucher to its buyer.
+    RefundVoucher {
+        code_hash: Binary,
+    },
+    /// Pays for drinks which are held for the recipient until `ClaimDrink`.
+    GiftDrink {
+        bev_type: String,
+        recipient: String,
+        quantity: Option<u8>,
+    },
+    ClaimDrink {
+        id: u64,
+    },
+    SetReservationBlocks {
+        blocks: u64,
+    },
+    /// Escrows the price and holds the stock for the configured number of blocks.
+    Reserve {
+        bev_type: String,
+        quantity: u8,
+    },
+    /// Completes the sale of a reservation.
+    Collect {
+        id: u64,
+    },
+    /// Releases the stock and refunds stale reservations, callable by anyone.
+    ExpireReservations {
+        limit: Option<u32>,
+    },
+    /// Pays for an order prepared by the baristas.
+    PlaceOrder {
+        bev_type: String,
+        quantity: Option<u8>,
+        #[serde(default)]
+        modifiers: Vec<String>,
+    },
+    /// Moves the order from placed to preparing, ready and collected, operators only.
+    AdvanceOrder {
+        id: u64,
+    },
+    /// Refunds an order which is not being prepared yet.
+    CancelOrder {
+        id: u64,
+    },
+    /// Pays for a sold out beverage which is held for the buyer as an
+    /// entitlement on the next refill.
+    Backorder {
+        bev_type: String,
+        quantity: Option<u8>,
+    },
+    CancelBackorder {
+        bev_type: String,
+        id: u64,
+    },
+    SetRefundWindow {
+        seconds: u64,
+    },
+    /// Sets the address which resolves refund requests along with the admin.
+    SetArbiter {
+        arbiter: Option<String>,
+    },
+    /// Disputes a purchase of the sender made within the refund window.
+    RequestRefund {
+        bev_type: String,
+        purchased_at: Timestamp,
+        reason: String,
+    },
+    ResolveRefund {
+        id: u64,
+        approve: bool,
+        /// Puts the undispensed items back into the stock. Items of an open
+        /// pickup are always released.
+        #[serde(default)]
+        restock: bool,
+    },
+    /// Lets `purchase` put what the balance can't cover on the tab of the address.
+    /// Removes the credit line if `limit` is not set.
+    SetCreditLimit {
+        address: String,
+        limit: Option<Uint128>,
+    },
+    /// Repays the tab of the sender, the whole debt by default.
+    SettleTab {
+        amount: Option<Uint128>,
+    },
+    /// Starts a purchase for the sender paid by the participants. The shares
+    /// must add up to the current total, which is charged on execution.
+    CreateGroupOrder {
+        bev_type: String,
+        quantity: Option<u8>,
+        #[serde(default)]
+        modifiers: Vec<String>,
+        shares: Vec<ShareRequest>,
+        expires_at: Timestamp,
+    },
+    /// Pays the share of the sender, the last share executes the purchase at the
+    /// quoted total.
+    FundGroupOrder {
+        id: u64,
+    },
+    /// Refunds the funded shares of an expired group order, callable by anyone.
+    ExpireGroupOrder {
+        id: u64,
+    },
+    SetPointsConfig {
+        config: Option<PointsConfig>,
+    },
+    RecordExternalSale {
+        bev_type: String,
+        quantity: u8,
+        amount: Uint128,
+        method: String,
+    },
+    UpdateOperators {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
     WithdrawIncome {},
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ShareRequest {
+    pub address: String,
+    pub amount: Uint128,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
-    Balance { address: String },
-    BeverageStat { bev_type: String },
+    Balance {
+        address: String,
+    },
+    BeverageStat {
+        bev_type: String,
+    },
+    IngredientStat {
+        ingredient: String,
+    },
+    Recipe {
+        bev_type: String,
+    },
+    /// Beverages and bundles with their prices and available amounts.
+    Menu {},
+    RestockReport {},
+    Batches {
+        bev_type: String,
+    },
+    PriceRules {
+        bev_type: String,
+    },
+    Quote {
+        bev_type: String,
+        quantity: u8,
+        #[serde(default)]
+        modifiers: Vec<String>,
+        buyer: Option<String>,
+        promo_code: Option<String>,
+    },
+    ScheduledPriceChanges {
+        bev_type: String,
+    },
+    LoyaltyStatus {
+        address: String,
+    },
+    Tier {
+        address: String,
+    },
+    SubscriptionPlans {},
+    Subscription {
+        address: String,
+    },
+    Pickup {
+        code_hash: Binary,
+    },
+    Voucher {
+        code_hash: Binary,
+    },
+    /// Drinks the address can claim.
+    Entitlements {
+        address: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    Reservations {
+        owner: Option<String>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Orders which are not collected yet in the order of placement.
+    OrderQueue {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    CustomerOrders {
+        customer: String,
+    },
+    /// Backorders of the beverage in the order they are served.
+    Backorders {
+        bev_type: String,
+    },
+    /// Refund requests which are not resolved yet.
+    Disputes {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Addresses which owe to the contract.
+    Tabs {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    GroupOrder {
+        id: u64,
+    },
+    Points {
+        address: String,
+    },
+    Revenue {},
+    InventoryLedger {
+        bev_type: Option<String>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RecipeResponse {
+    pub recipe: Vec<RecipeItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RestockItem {
+    pub name: String,
+    pub amount: Uint128,
+    pub threshold: Uint128,
+    /// Amount which brings the stock back to full capacity.
+    pub suggested_refill: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RestockReportResponse {
+    pub beverages: Vec<RestockItem>,
+    pub ingredients: Vec<RestockItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BatchesResponse {
+    pub batches: Vec<Batch>,
+    /// Number of items which are expired at the current block time.
+    pub expired: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InventoryLedgerResponse {
+    pub adjustments: Vec<Adjustment>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MethodRevenue {
+    pub method: String,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RevenueResponse {
+    pub on_chain: Uint128,
+    pub off_chain: Uint128,
+    pub off_chain_by_method: Vec<MethodRevenue>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PriceRulesResponse {
+    pub rules: Vec<PriceRule>,
+    pub utc_offset: i16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ScheduledPriceChangesResponse {
+    pub changes: Vec<PriceChange>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QuoteResponse {
+    /// Price set by the admin.
+    pub base_price: Uint128,
+    /// Price of one item right now, without modifiers.
+    pub price: Uint128,
+    /// Index of the applied time-of-day price rule.
+    pub price_rule: Option<u32>,
+    /// Minimal quantity of the applied price break.
+    pub price_break: Option<u8>,
+    /// Surcharge of the modifiers for one item.
+    pub modifiers_price: Uint128,
+    pub quantity: u8,
+    /// Number of items which are free thanks to the loyalty card.
+    pub free_items: u8,
+    /// Number of items covered by the buyer subscription.
+    pub subscription_items: u8,
+    /// Amount taken off by the promo code.
+    pub promo_discount: Uint128,
+    /// Amount taken off by the buyer tier.
+    pub tier_discount: Uint128,
+    /// Amount the buyer pays.
+    pub total: Uint128,
+    pub in_stock: bool,
+    /// Whether the buyer balance and the credit left on the tab cover the
+    /// total, if the buyer is given.
+    pub enough_balance: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LoyaltyStatusResponse {
+    pub stamps: u32,
+    pub rule: Option<LoyaltyRule>,
+    /// Number of beverages to buy until the next free one.
+    pub remaining: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TierResponse {
+    pub tier: Option<Tier>,
+    /// Spend within the last 30 days.
+    pub spent: Uint128,
+    pub next_tier: Option<Tier>,
+    /// Spend required to reach the next tier.
+    pub amount_to_next_tier: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PointsResponse {
+    /// Points which are not expired yet.
+    pub balance: Uint128,
+    pub grants: Vec<PointsGrant>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MenuBeverage {
+    pub bev_type: String,
+    pub price: Uint128,
+    pub available: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MenuBundle {
+    pub name: String,
+    pub price: Uint128,
+    pub items: Vec<BundleItem>,
+    pub available: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MenuResponse {
+    pub beverages: Vec<MenuBeverage>,
+    pub bundles: Vec<MenuBundle>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SubscriptionPlanInfo {
+    pub name: String,
+    pub plan: SubscriptionPlan,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SubscriptionPlansResponse {
+    pub plans: Vec<SubscriptionPlanInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SubscriptionResponse {
+    pub subscription: Option<Subscription>,
+    /// Drinks left for the current month.
+    pub remaining: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct EntitlementsResponse {
+    pub entitlements: Vec<Entitlement>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReservationsResponse {
+    pub reservations: Vec<Reservation>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OrdersResponse {
+    pub orders: Vec<BaristaOrder>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BackordersResponse {
+    pub backorders: Vec<Backorder>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DisputesResponse {
+    pub disputes: Vec<Dispute>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TabInfo {
+    pub address: String,
+    pub debt: Uint128,
+    pub limit: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TabsResponse {
+    pub tabs: Vec<TabInfo>,
 }
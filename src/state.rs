@@ -1,5 +1,5 @@
-use cosmwasm_std::{Addr, Uint128};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, Binary, Empty, StdError, Timestamp, Uint128};
+use cw_storage_plus::{Item, Map, U64Key};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -9,28 +9,840 @@ use crate::ContractError;
 pub struct BeverageStat {
     pub price: Uint128,
     pub amount: u8,
+    /// A purchase leaving `amount` at or below this value raises a `low_stock` event.
+    pub low_stock: Option<u8>,
+    /// Refilled items in the order they were loaded into the machine.
+    pub batches: Vec<Batch>,
+    pub category: Option<String>,
+    /// Quantity price breaks in ascending order of `min_quantity`.
+    pub price_breaks: Vec<PriceBreak>,
+    /// Items taken out of `amount` by `hold` which are still in the machine.
+    pub held: u8,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Batch {
+    pub amount: u8,
+    pub refilled_at: Timestamp,
+    pub expires_at: Option<Timestamp>,
+}
+
+impl Batch {
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+}
+
+/// Maximum number of items of one beverage the machine can hold.
+pub const BEVERAGE_CAPACITY: u8 = 50;
+
 impl BeverageStat {
-    pub fn refill(mut self, amount: u8) -> Result<Self, ContractError> {
-        if self.amount + amount > 50 {
+    pub fn refill(
+        mut self,
+        amount: u8,
+        now: Timestamp,
+        expires_at: Option<Timestamp>,
+    ) -> Result<Self, ContractError> {
+        if matches!(expires_at, Some(expires_at) if expires_at <= now) {
+            return Err(ContractError::PastExpiry {});
+        }
+        let total = self
+            .amount
+            .checked_add(self.held)
+            .and_then(|total| total.checked_add(amount))
+            .unwrap_or(u8::MAX);
+        if total > BEVERAGE_CAPACITY {
             Err(ContractError::BeverageNumberExceed {})
         } else {
             self.amount += amount;
+            self.batches.push(Batch {
+                amount,
+                refilled_at: now,
+                expires_at,
+            });
             Ok(self)
         }
     }
 
-    pub fn sell(mut self) -> Result<Self, ContractError> {
-        if let Some(amount) = self.amount.checked_sub(1) {
+    /// Sells one item from the oldest batch which is not expired yet.
+    pub fn sell(mut self, now: Timestamp) -> Result<Self, ContractError> {
+        if self.amount == 0 {
+            return Err(ContractError::BeverageIsOver {});
+        }
+
+        let index = self
+            .batches
+            .iter()
+            .position(|batch| !batch.is_expired(now))
+            .ok_or(ContractError::BeverageExpired {})?;
+        self.batches[index].amount -= 1;
+        if self.batches[index].amount == 0 {
+            self.batches.remove(index);
+        }
+        self.amount -= 1;
+        Ok(self)
+    }
+
+    /// Removes all expired batches and returns the number of written off items.
+    pub fn write_off_expired(&mut self, now: Timestamp) -> u8 {
+        let expired = self.expired(now);
+        self.batches.retain(|batch| !batch.is_expired(now));
+        self.amount -= expired;
+        expired
+    }
+
+    /// Takes items out of the machine starting from the oldest batch,
+    /// regardless of their expiration.
+    pub fn remove(mut self, amount: u8) -> Result<Self, ContractError> {
+        if amount > self.amount {
+            return Err(ContractError::BeverageIsOver {});
+        }
+
+        let mut left = amount;
+        while left > 0 {
+            let taken = left.min(self.batches[0].amount);
+            self.batches[0].amount -= taken;
+            if self.batches[0].amount == 0 {
+                self.batches.remove(0);
+            }
+            left -= taken;
+        }
+        self.amount -= amount;
+        Ok(self)
+    }
+
+    /// Takes items out of the oldest batches which are not expired yet and
+    /// returns them as batches, so that `release` can put them back.
+    pub fn hold(&mut self, amount: u8, now: Timestamp) -> Result<Vec<Batch>, ContractError> {
+        if amount > self.amount {
+            return Err(ContractError::BeverageIsOver {});
+        }
+        if amount > self.available(now) {
+            return Err(ContractError::BeverageExpired {});
+        }
+
+        let mut held = vec![];
+        let mut left = amount;
+        let mut index = 0;
+        while left > 0 {
+            let batch = &mut self.batches[index];
+            if batch.is_expired(now) {
+                index += 1;
+                continue;
+            }
+            let taken = left.min(batch.amount);
+            batch.amount -= taken;
+            held.push(Batch {
+                amount: taken,
+                refilled_at: batch.refilled_at,
+                expires_at: batch.expires_at,
+            });
+            if batch.amount == 0 {
+                self.batches.remove(index);
+            } else {
+                index += 1;
+            }
+            left -= taken;
+        }
+        self.amount -= amount;
+        self.held += amount;
+        Ok(held)
+    }
+
+    /// Puts back the items taken by `hold`, keeping the loading order of the batches.
+    pub fn release(&mut self, batches: Vec<Batch>) {
+        for batch in batches {
+            self.amount += batch.amount;
+            self.held -= batch.amount;
+            let index = self
+                .batches
+                .iter()
+                .position(|other| other.refilled_at > batch.refilled_at)
+                .unwrap_or(self.batches.len());
+            self.batches.insert(index, batch);
+        }
+    }
+
+    /// Hands out items taken by `hold`.
+    pub fn dispense_held(&mut self, amount: u8) {
+        self.held -= amount;
+    }
+
+    /// Number of items which can still be sold.
+    pub fn available(&self, now: Timestamp) -> u8 {
+        self.amount - self.expired(now)
+    }
+
+    pub fn expired(&self, now: Timestamp) -> u8 {
+        self.batches
+            .iter()
+            .filter(|batch| batch.is_expired(now))
+            .map(|batch| batch.amount)
+            .sum()
+    }
+
+    pub fn is_low(&self) -> bool {
+        matches!(self.low_stock, Some(threshold) if self.amount <= threshold)
+    }
+
+    /// The break with the largest `min_quantity` which the quantity reaches.
+    pub fn price_break(&self, quantity: u8) -> Option<&PriceBreak> {
+        self.price_breaks
+            .iter()
+            .rev()
+            .find(|price_break| price_break.min_quantity <= quantity)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct IngredientStat {
+    pub amount: Uint128,
+    pub capacity: Uint128,
+    /// A purchase leaving `amount` at or below this value raises a `low_stock` event.
+    pub low_stock: Option<Uint128>,
+}
+
+impl IngredientStat {
+    pub fn refill(mut self, amount: Uint128) -> Result<Self, ContractError> {
+        let amount = self.amount.checked_add(amount).map_err(StdError::from)?;
+        if amount > self.capacity {
+            Err(ContractError::IngredientCapacityExceed {})
+        } else {
             self.amount = amount;
             Ok(self)
+        }
+    }
+
+    pub fn consume(mut self, ingredient: &str, amount: Uint128) -> Result<Self, ContractError> {
+        self.amount =
+            self.amount
+                .checked_sub(amount)
+                .map_err(|_| ContractError::IngredientIsOver {
+                    ingredient: ingredient.to_string(),
+                })?;
+        Ok(self)
+    }
+
+    pub fn is_low(&self) -> bool {
+        matches!(self.low_stock, Some(threshold) if self.amount <= threshold)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RecipeItem {
+    pub ingredient: String,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AdjustmentReason {
+    Spoilage,
+    Breakage,
+    Theft,
+    AuditCorrection,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Adjustment {
+    pub id: u64,
+    pub bev_type: String,
+    pub delta: i16,
+    pub reason: AdjustmentReason,
+    pub by: Addr,
+    pub at: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Discount {
+    Percent { percent: u8 },
+    Fixed { amount: Uint128 },
+}
+
+impl Discount {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        match self {
+            Discount::Percent { percent } if *percent > 100 => {
+                Err(ContractError::InvalidDiscount {})
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn apply(&self, price: Uint128) -> Uint128 {
+        match self {
+            Discount::Percent { percent } => price - price.multiply_ratio(*percent, 100_u8),
+            Discount::Fixed { amount } => price.saturating_sub(*amount),
+        }
+    }
+}
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// Discount which is active on the given weekdays within a window of the day.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PriceRule {
+    /// Bit 0 is Monday, bit 6 is Sunday.
+    pub weekdays: u8,
+    /// Minute of the local day the rule starts at, inclusive.
+    pub start_minute: u16,
+    /// Minute of the local day the rule ends at, exclusive.
+    /// The window wraps over midnight if it is less than `start_minute`.
+    pub end_minute: u16,
+    pub discount: Discount,
+}
+
+impl PriceRule {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        if self.weekdays >= 1 << 7
+            || self.start_minute >= MINUTES_PER_DAY
+            || self.end_minute > MINUTES_PER_DAY
+        {
+            return Err(ContractError::InvalidPriceRule {});
+        }
+        self.discount.validate()
+    }
+
+    /// `utc_offset` is the offset of the local time zone in minutes.
+    pub fn is_active(&self, now: Timestamp, utc_offset: i16) -> bool {
+        let local = now.seconds() as i64 + i64::from(utc_offset) * 60;
+        let days = local.div_euclid(86400);
+        // 1970-01-01 was Thursday
+        let weekday = (days + 3).rem_euclid(7);
+        let minute = (local.rem_euclid(86400) / 60) as u16;
+
+        let in_window = if self.start_minute <= self.end_minute {
+            self.start_minute <= minute && minute < self.end_minute
+        } else {
+            self.start_minute <= minute || minute < self.end_minute
+        };
+        self.weekdays & (1 << weekday) != 0 && in_window
+    }
+}
+
+/// Number of months since January 1970 in the local time zone.
+pub fn local_month(now: Timestamp, utc_offset: i16) -> u32 {
+    let local = now.seconds() as i64 + i64::from(utc_offset) * 60;
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let days = local.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let (year, month) = if month < 10 {
+        (year_of_era + era * 400, month + 2)
+    } else {
+        (year_of_era + era * 400 + 1, month - 10)
+    };
+    ((year - 1970) * 12 + month).max(0) as u32
+}
+
+/// The moment the local month returned by `local_month` starts.
+pub fn month_start(month: u32, utc_offset: i16) -> Timestamp {
+    let (year, month) = (1970 + i64::from(month / 12), i64::from(month % 12));
+    // days from civil with March as the first month of the year
+    let year = if month < 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 10) % 12) + 2) / 5;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Timestamp::from_seconds((days * 86400 - i64::from(utc_offset) * 60).max(0) as u64)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ScarcityStep {
+    /// The step applies once the stock drops to this amount.
+    pub amount: u8,
+    pub markup_percent: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScarcityCurve {
+    /// Markup grows evenly from zero at full capacity up to `max_markup_percent` when empty.
+    Linear { max_markup_percent: u16 },
+    /// The largest markup among the reached steps is applied.
+    Stepped { steps: Vec<ScarcityStep> },
+}
+
+/// Marks the price up as the beverage stock runs out.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ScarcityPricing {
+    pub curve: ScarcityCurve,
+    pub floor: Uint128,
+    pub ceiling: Uint128,
+}
+
+impl ScarcityPricing {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        if self.floor > self.ceiling {
+            Err(ContractError::InvalidScarcityPricing {})
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn apply(&self, price: Uint128, amount: u8) -> Uint128 {
+        let missing = BEVERAGE_CAPACITY.saturating_sub(amount);
+        let markup = match &self.curve {
+            ScarcityCurve::Linear { max_markup_percent } => price.multiply_ratio(
+                u32::from(*max_markup_percent) * u32::from(missing),
+                100 * u32::from(BEVERAGE_CAPACITY),
+            ),
+            ScarcityCurve::Stepped { steps } => {
+                let percent = steps
+                    .iter()
+                    .filter(|step| amount <= step.amount)
+                    .map(|step| step.markup_percent)
+                    .max()
+                    .unwrap_or_default();
+                price.multiply_ratio(percent, 100_u8)
+            }
+        };
+        (price + markup).max(self.floor).min(self.ceiling)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PromoCode {
+    pub discount: Discount,
+    /// The code applies to any beverage if empty.
+    pub beverages: Vec<String>,
+    pub max_uses: Option<u32>,
+    pub max_uses_per_address: Option<u32>,
+    pub expires_at: Option<Timestamp>,
+    pub uses: u32,
+}
+
+impl PromoCode {
+    pub fn check(
+        &self,
+        bev_type: &str,
+        now: Timestamp,
+        address_uses: u32,
+    ) -> Result<(), ContractError> {
+        if !self.beverages.is_empty() && !self.beverages.iter().any(|bev| bev == bev_type) {
+            return Err(ContractError::InvalidPromoCode {});
+        }
+        if matches!(self.expires_at, Some(expires_at) if expires_at <= now) {
+            return Err(ContractError::PromoCodeExpired {});
+        }
+        if matches!(self.max_uses, Some(max_uses) if self.uses >= max_uses)
+            || matches!(self.max_uses_per_address, Some(max_uses) if address_uses >= max_uses)
+        {
+            return Err(ContractError::PromoCodeExhausted {});
+        }
+        Ok(())
+    }
+}
+
+/// Every `every`-th beverage of the category is free.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LoyaltyRule {
+    pub every: u32,
+    /// The rule applies to any beverage if not set.
+    pub category: Option<String>,
+}
+
+impl LoyaltyRule {
+    pub fn applies(&self, stat: &BeverageStat) -> bool {
+        self.category.is_none() || self.category == stat.category
+    }
+
+    /// Number of free items among `quantity` items bought with `stamps` on the card.
+    pub fn free_items(&self, stamps: u32, quantity: u8) -> u8 {
+        let stamped = stamps + u32::from(quantity);
+        (stamped / self.every - stamps / self.every) as u8
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Tier {
+    Bronze,
+    Silver,
+    Gold,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TierConfig {
+    /// Spend within the window which is required for the tier.
+    pub min_spend: Uint128,
+    pub discount_percent: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TiersConfig {
+    pub bronze: TierConfig,
+    pub silver: TierConfig,
+    pub gold: TierConfig,
+}
+
+impl TiersConfig {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        if self.bronze.min_spend > self.silver.min_spend
+            || self.silver.min_spend > self.gold.min_spend
+            || self
+                .tiers()
+                .any(|(_, config)| config.discount_percent > 100)
+        {
+            Err(ContractError::InvalidTiers {})
         } else {
-            Err(ContractError::BeverageIsOver {})
+            Ok(())
         }
     }
+
+    pub fn tiers(&self) -> impl Iterator<Item = (Tier, &TierConfig)> {
+        vec![
+            (Tier::Bronze, &self.bronze),
+            (Tier::Silver, &self.silver),
+            (Tier::Gold, &self.gold),
+        ]
+        .into_iter()
+    }
+
+    pub fn tier(&self, spent: Uint128) -> Option<(Tier, &TierConfig)> {
+        self.tiers()
+            .filter(|(_, config)| spent >= config.min_spend)
+            .last()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Spending {
+    pub amount: Uint128,
+    pub at: Timestamp,
+}
+
+/// Tiers are computed from the spend within this window.
+pub const SPEND_WINDOW_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+/// Discount on the unit price when buying at least `min_quantity` items at once.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PriceBreak {
+    pub min_quantity: u8,
+    pub discount: Discount,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BundleItem {
+    pub bev_type: String,
+    pub quantity: u8,
+}
+
+/// Several beverages sold together for less than the sum of their prices.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Bundle {
+    pub price: Uint128,
+    pub items: Vec<BundleItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SubscriptionPlan {
+    /// Price of one month.
+    pub price: Uint128,
+    /// Number of drinks per calendar month.
+    pub drinks: u32,
+    /// Categories of the covered beverages, any beverage if empty.
+    pub categories: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Subscription {
+    pub plan: String,
+    pub drinks: u32,
+    pub categories: Vec<String>,
+    pub started_at: Timestamp,
+    pub expires_at: Timestamp,
+    /// Month the `used` counter refers to, see `local_month`.
+    pub month: u32,
+    pub used: u32,
+}
+
+impl Subscription {
+    pub fn covers(&self, stat: &BeverageStat) -> bool {
+        self.categories.is_empty()
+            || matches!(&stat.category, Some(category) if self.categories.contains(category))
+    }
+
+    /// Drinks left for the current month.
+    pub fn remaining(&self, now: Timestamp, utc_offset: i16) -> u32 {
+        if now >= self.expires_at {
+            0
+        } else if local_month(now, utc_offset) != self.month {
+            self.drinks
+        } else {
+            self.drinks.saturating_sub(self.used)
+        }
+    }
+
+    pub fn consume(&mut self, now: Timestamp, utc_offset: i16, drinks: u32) {
+        let month = local_month(now, utc_offset);
+        if month != self.month {
+            self.month = month;
+            self.used = 0;
+        }
+        self.used += drinks;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum VoucherValue {
+    Beverage { bev_type: String },
+    Credit { amount: Uint128 },
+}
+
+/// Prepaid gift redeemable by whoever knows the code.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Voucher {
+    pub buyer: Addr,
+    pub value: VoucherValue,
+    /// Coins held by the voucher until it is redeemed or refunded.
+    pub paid: Uint128,
+    pub expires_at: Timestamp,
+}
+
+/// Paid drinks which the holder can claim from the machine later.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Entitlement {
+    pub id: u64,
+    pub bev_type: String,
+    pub quantity: u8,
+    /// Address which paid for the drinks.
+    pub from: Addr,
+    pub created_at: Timestamp,
+    /// Items already taken out of the stock for the holder, empty if the
+    /// drinks are taken from the stock on claim.
+    pub batches: Vec<Batch>,
+}
+
+/// Paid purchase waiting in line for the next refill of a sold out beverage.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Backorder {
+    pub id: u64,
+    pub buyer: Addr,
+    pub bev_type: String,
+    pub quantity: u8,
+    /// Coins held by the backorder until it is served or cancelled.
+    pub paid: Uint128,
+    pub placed_at: Timestamp,
+}
+
+/// Prepaid stock held for the owner until `expires_at_height`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Reservation {
+    pub id: u64,
+    pub owner: Addr,
+    pub bev_type: String,
+    pub quantity: u8,
+    /// Coins held by the reservation until it is collected or expires.
+    pub paid: Uint128,
+    /// Items taken out of the beverage stock.
+    pub batches: Vec<Batch>,
+    pub expires_at_height: u64,
+}
+
+impl Reservation {
+    /// Whether some of the held items went off before collection.
+    pub fn has_expired_items(&self, now: Timestamp) -> bool {
+        self.batches.iter().any(|batch| batch.is_expired(now))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Placed,
+    Preparing,
+    Ready,
+    Collected,
+    Cancelled,
+}
+
+/// Order made at a staffed counter. Orders are removed from the storage once
+/// they are collected or cancelled.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BaristaOrder {
+    pub id: u64,
+    pub customer: Addr,
+    pub bev_type: String,
+    pub quantity: u8,
+    pub modifiers: Vec<String>,
+    /// Coins held by the order until the barista starts preparing it.
+    pub paid: Uint128,
+    pub status: OrderStatus,
+    pub placed_at: Timestamp,
+}
+
+/// Purchase kept for a while so that the buyer can ask for a refund.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PurchaseRecord {
+    pub bev_type: String,
+    pub quantity: u8,
+    /// Coins paid, without the points.
+    pub paid: Uint128,
+    /// Points spent instead of coins.
+    pub points: Uint128,
+    /// Drinks covered by the subscription of the buyer.
+    pub subscription_items: u8,
+    pub promo_code_hash: Option<Binary>,
+    /// Part of the price put on the tab of the buyer.
+    pub on_tab: Uint128,
+    /// Points credited as cashback.
+    pub cashback: Uint128,
+    /// Loyalty stamps added by the purchase.
+    pub stamps: u32,
+    /// Pickup holding the items if they were bought with a pickup code.
+    pub pickup_code_hash: Option<Binary>,
+    pub purchased_at: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Dispute {
+    pub id: u64,
+    pub buyer: Addr,
+    pub purchase: PurchaseRecord,
+    pub reason: String,
+    pub opened_at: Timestamp,
+}
+
+/// Refunds can be requested within this number of seconds after the purchase
+/// unless the admin changes it.
+pub const DEFAULT_REFUND_WINDOW_SECONDS: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GroupShare {
+    pub address: Addr,
+    pub amount: Uint128,
+    pub funded: bool,
+}
+
+/// Purchase split between several addresses, executed once every share is funded.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GroupOrder {
+    pub id: u64,
+    pub initiator: Addr,
+    pub bev_type: String,
+    pub quantity: u8,
+    pub modifiers: Vec<String>,
+    /// Unit price quoted when the order was created.
+    pub price: Uint128,
+    /// Total quoted when the order was created, charged as is on execution.
+    pub total: Uint128,
+    pub shares: Vec<GroupShare>,
+    pub expires_at: Timestamp,
+}
+
+/// Number of blocks a reservation is held for unless the admin changes it.
+pub const DEFAULT_RESERVATION_BLOCKS: u64 = 100;
+
+/// Pickup codes are committed together with a secret salt of at least this
+/// many bytes, so that short codes can't be guessed from the public hashes.
+pub const MIN_PICKUP_SALT_LENGTH: usize = 16;
+
+/// Paid purchase waiting for the pickup code to be revealed at the counter.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Pickup {
+    pub buyer: Addr,
+    pub bev_type: String,
+    pub quantity: u8,
+    pub paid_at: Timestamp,
+    /// Items held in the machine for the buyer.
+    pub batches: Vec<Batch>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PointsConfig {
+    /// Share of the coins paid for a purchase which is credited as points.
+    pub cashback_percent: u8,
+    /// Points expire after this number of seconds if set.
+    pub expires_after: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PointsGrant {
+    pub amount: Uint128,
+    pub expires_at: Option<Timestamp>,
+}
+
+impl PointsGrant {
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PriceChange {
+    pub new_price: Uint128,
+    pub effective_at: Timestamp,
 }
 
 pub const BEVERAGES: Map<&str, BeverageStat> = Map::new("beverages");
 pub const BALANCES: Map<&Addr, Uint128> = Map::new("balance");
 pub const ADMIN: Item<Addr> = Item::new("admin");
+pub const INGREDIENTS: Map<&str, IngredientStat> = Map::new("ingredients");
+pub const RECIPES: Map<&str, Vec<RecipeItem>> = Map::new("recipes");
+pub const ADJUSTMENTS: Map<U64Key, Adjustment> = Map::new("adjustments");
+pub const ADJUSTMENT_COUNT: Item<u64> = Item::new("adjustment_count");
+pub const OPERATORS: Map<&Addr, Empty> = Map::new("operators");
+/// Income collected by the contract from `Purchase`.
+pub const ON_CHAIN_REVENUE: Item<Uint128> = Item::new("on_chain_revenue");
+/// Income taken at the counter, keyed by the payment method.
+pub const OFF_CHAIN_REVENUE: Map<&str, Uint128> = Map::new("off_chain_revenue");
+pub const PRICE_RULES: Map<&str, Vec<PriceRule>> = Map::new("price_rules");
+/// Offset of the café time zone from UTC in minutes, used by the price rules.
+pub const UTC_OFFSET: Item<i16> = Item::new("utc_offset");
+/// Announced price changes ordered by `effective_at`.
+pub const PRICE_CHANGES: Map<&str, Vec<PriceChange>> = Map::new("price_changes");
+pub const SCARCITY_PRICING: Map<&str, ScarcityPricing> = Map::new("scarcity_pricing");
+/// Surcharge for one item, keyed by the modifier name (e.g. "extra_shot").
+pub const MODIFIERS: Map<&str, Uint128> = Map::new("modifiers");
+/// Promo codes keyed by the SHA-256 hash of the code, so the codes are not readable on-chain.
+pub const PROMO_CODES: Map<&[u8], PromoCode> = Map::new("promo_codes");
+pub const PROMO_CODE_USES: Map<(&[u8], &Addr), u32> = Map::new("promo_code_uses");
+pub const LOYALTY_RULE: Item<Option<LoyaltyRule>> = Item::new("loyalty_rule");
+/// Stamps collected by the customer since the last free beverage.
+pub const LOYALTY_CARDS: Map<&Addr, u32> = Map::new("loyalty_cards");
+pub const TIERS: Item<Option<TiersConfig>> = Item::new("tiers");
+/// Purchases of the customer within the spend window.
+pub const SPENDINGS: Map<&Addr, Vec<Spending>> = Map::new("spendings");
+pub const POINTS_CONFIG: Item<Option<PointsConfig>> = Item::new("points_config");
+/// Non-transferable reward points, separate from the coin `BALANCES`.
+pub const POINTS: Map<&Addr, Vec<PointsGrant>> = Map::new("points");
+pub const BUNDLES: Map<&str, Bundle> = Map::new("bundles");
+pub const SUBSCRIPTION_PLANS: Map<&str, SubscriptionPlan> = Map::new("subscription_plans");
+pub const SUBSCRIPTIONS: Map<&Addr, Subscription> = Map::new("subscriptions");
+/// Vouchers by the SHA-256 hash of their code.
+pub const VOUCHERS: Map<&[u8], Voucher> = Map::new("vouchers");
+/// Entitlements by the holder address and id.
+pub const ENTITLEMENTS: Map<(&Addr, U64Key), Entitlement> = Map::new("entitlements");
+pub const ENTITLEMENT_COUNT: Item<u64> = Item::new("entitlement_count");
+pub const RESERVATIONS: Map<U64Key, Reservation> = Map::new("reservations");
+pub const RESERVATION_COUNT: Item<u64> = Item::new("reservation_count");
+pub const RESERVATION_BLOCKS: Item<u64> = Item::new("reservation_blocks");
+pub const BARISTA_ORDERS: Map<U64Key, BaristaOrder> = Map::new("barista_orders");
+pub const BARISTA_ORDER_COUNT: Item<u64> = Item::new("barista_order_count");
+/// Pickups by the SHA-256 hash of their code.
+pub const PICKUPS: Map<&[u8], Pickup> = Map::new("pickups");
+/// Backorders by the beverage and id, so that each beverage has its own queue.
+pub const BACKORDERS: Map<(&str, U64Key), Backorder> = Map::new("backorders");
+pub const BACKORDER_COUNT: Item<u64> = Item::new("backorder_count");
+pub const REFUND_WINDOW: Item<u64> = Item::new("refund_window");
+/// Resolves refund requests along with the admin.
+pub const ARBITER: Item<Option<Addr>> = Item::new("arbiter");
+/// Purchases of every buyer made within the refund window.
+pub const PURCHASES: Map<&Addr, Vec<PurchaseRecord>> = Map::new("purchases");
+/// Open refund requests.
+pub const DISPUTES: Map<U64Key, Dispute> = Map::new("disputes");
+pub const DISPUTE_COUNT: Item<u64> = Item::new("dispute_count");
+/// Amount an address may owe to the contract.
+pub const CREDIT_LIMITS: Map<&Addr, Uint128> = Map::new("credit_limits");
+/// Amount an address owes to the contract, kept apart from `BALANCES`.
+pub const TABS: Map<&Addr, Uint128> = Map::new("tabs");
+pub const GROUP_ORDERS: Map<U64Key, GroupOrder> = Map::new("group_orders");
+pub const GROUP_ORDER_COUNT: Item<u64> = Item::new("group_order_count");